@@ -54,6 +54,26 @@ impl Aabb {
         true
     }
 
+    pub fn hit_interval(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64)> {
+        // Like hit(), but returns the parametric entry and exit of the ray.
+        let mut t_min = t_min;
+        let mut t_max = t_max;
+        for a in 0..3 {
+            let inv_d: f64 = 1.0 / r.direction().e[a];
+            let mut t0: f64 = (self.minimum().e[a] - r.origin().e[a]) * inv_d;
+            let mut t1: f64 = (self.maximum().e[a] - r.origin().e[a]) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            t_min = t_min.max(t0);
+            t_max = t_max.min(t1);
+            if t_max <= t_min {
+                return None;
+            }
+        }
+        Some((t_min, t_max))
+    }
+
     pub fn surrounding_box(box0: &Self, box1: &Self) -> Self {
         let small: Point3 = Point3::construct(&[
             box0.minimum().x().min(box1.minimum().x()),
@@ -42,6 +42,10 @@ impl Hittable for XyRect {
         rec.v = (y - self.y0) / (self.y1 - self.y0);
         rec.t = t;
         rec.set_face_normal(r, &Vec3::construct(&[0.0, 0.0, 1.0]));
+        rec.set_uv_derivatives(
+            &Vec3::construct(&[self.x1 - self.x0, 0.0, 0.0]),
+            &Vec3::construct(&[0.0, self.y1 - self.y0, 0.0]),
+        );
         rec.mat_ptr = Some(Arc::clone(&self.mp));
         rec.p = r.at(t);
         true
@@ -92,6 +96,10 @@ impl Hittable for XzRect {
         rec.v = (z - self.z0) / (self.z1 - self.z0);
         rec.t = t;
         rec.set_face_normal(r, &Vec3::construct(&[0.0, 1.0, 0.0]));
+        rec.set_uv_derivatives(
+            &Vec3::construct(&[self.x1 - self.x0, 0.0, 0.0]),
+            &Vec3::construct(&[0.0, 0.0, self.z1 - self.z0]),
+        );
         rec.mat_ptr = Some(Arc::clone(&self.mp));
         rec.p = r.at(t);
         true
@@ -104,18 +112,18 @@ impl Hittable for XzRect {
         );
         true
     }
-    fn pdf_value(&self, origin: &Point3, v: &Vec3) -> f64 {
+    fn pdf_value(&self, origin: &Point3, v: &Vec3, time: f64) -> f64 {
         let mut rec = HitRecord::new();
-        if !self.hit(&Ray::construct(origin, v, 0.0), 0.001, INFINITY, &mut rec) {
+        if !self.hit(&Ray::construct(origin, v, time), 0.001, INFINITY, &mut rec) {
             return 0.0;
         }
 
         let area = (self.x1 - self.x0) * (self.z1 - self.z0);
         let distance_squared = rec.t * rec.t * v.length_squared();
-        let cosine = (dot(&v, &rec.normal) / v.length()).abs();
+        let cosine = (dot(v, &rec.normal) / v.length()).abs();
         distance_squared / cosine / area
     }
-    fn random(&self, origin: &Vec3) -> Vec3 {
+    fn random(&self, origin: &Vec3, _time: f64) -> Vec3 {
         let random_point = Point3::construct(&[
             random_double_range(self.x0, self.x1),
             self.k,
@@ -161,6 +169,10 @@ impl Hittable for YzRect {
         rec.v = (z - self.z0) / (self.z1 - self.z0);
         rec.t = t;
         rec.set_face_normal(r, &Vec3::construct(&[1.0, 0.0, 0.0]));
+        rec.set_uv_derivatives(
+            &Vec3::construct(&[0.0, self.y1 - self.y0, 0.0]),
+            &Vec3::construct(&[0.0, 0.0, self.z1 - self.z0]),
+        );
         rec.mat_ptr = Some(Arc::clone(&self.mp));
         rec.p = r.at(t);
         true
@@ -0,0 +1,350 @@
+use crate::camera::{Camera, CameraBuilder, PerspectiveCamera};
+use crate::ray::Ray;
+use crate::rt_weekend::random_double_range;
+use crate::transform::Transform;
+use crate::vec3::Vec3;
+use std::fmt::Debug;
+use std::ops::{Add, Mul, Sub};
+
+// Values a Track can interpolate.
+pub trait Animatable:
+    Copy + Debug + Send + Sync + Add<Output = Self> + Sub<Output = Self> + Mul<f64, Output = Self>
+{
+}
+impl Animatable for f64 {}
+impl Animatable for Vec3 {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interpolation {
+    Linear,
+    CatmullRom,
+    // Cubic Bezier per segment; keys without handles get Catmull-Rom ones.
+    Bezier,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Keyframe<T: Animatable> {
+    pub time: f64,
+    pub value: T,
+    pub in_handle: Option<T>,
+    pub out_handle: Option<T>,
+}
+impl<T: Animatable> Keyframe<T> {
+    pub fn construct(time: f64, value: T) -> Self {
+        Self {
+            time,
+            value,
+            in_handle: None,
+            out_handle: None,
+        }
+    }
+    // Bezier control points before and after this key, as absolute values.
+    pub fn with_handles(mut self, in_handle: T, out_handle: T) -> Self {
+        self.in_handle = Some(in_handle);
+        self.out_handle = Some(out_handle);
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Track<T: Animatable> {
+    pub keys: Vec<Keyframe<T>>,
+    pub interpolation: Interpolation,
+}
+impl<T: Animatable> Track<T> {
+    pub fn construct(keys: &[Keyframe<T>], interpolation: Interpolation) -> Self {
+        assert!(!keys.is_empty(), "A track needs at least one key.");
+        let mut keys = keys.to_vec();
+        keys.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+        Self {
+            keys,
+            interpolation,
+        }
+    }
+    pub fn constant(value: T) -> Self {
+        Self::construct(&[Keyframe::construct(0.0, value)], Interpolation::Linear)
+    }
+
+    fn tangent(&self, i: usize) -> T {
+        // Catmull-Rom slope at key i, per unit time; one-sided at the ends.
+        let keys = &self.keys;
+        let last = keys.len() - 1;
+        let (a, b) = if i == 0 {
+            (0, 1)
+        } else if i == last {
+            (last - 1, last)
+        } else {
+            (i - 1, i + 1)
+        };
+        (keys[b].value - keys[a].value) * (1.0 / (keys[b].time - keys[a].time))
+    }
+    pub fn sample(&self, time: f64) -> T {
+        // Holds the first and last values outside the keyed range.
+        let keys = &self.keys;
+        if keys.len() == 1 || time <= keys[0].time {
+            return keys[0].value;
+        }
+        if time >= keys[keys.len() - 1].time {
+            return keys[keys.len() - 1].value;
+        }
+        let i = keys.iter().rposition(|k| k.time <= time).unwrap();
+        let (k0, k1) = (&keys[i], &keys[i + 1]);
+        let dt = k1.time - k0.time;
+        let u = (time - k0.time) / dt;
+        match self.interpolation {
+            Interpolation::Linear => k0.value * (1.0 - u) + k1.value * u,
+            Interpolation::CatmullRom => {
+                let m0 = self.tangent(i) * dt;
+                let m1 = self.tangent(i + 1) * dt;
+                let u2 = u * u;
+                let u3 = u2 * u;
+                k0.value * (2.0 * u3 - 3.0 * u2 + 1.0)
+                    + m0 * (u3 - 2.0 * u2 + u)
+                    + k1.value * (-2.0 * u3 + 3.0 * u2)
+                    + m1 * (u3 - u2)
+            }
+            Interpolation::Bezier => {
+                let c1 = k0
+                    .out_handle
+                    .unwrap_or_else(|| k0.value + self.tangent(i) * (dt / 3.0));
+                let c2 = k1
+                    .in_handle
+                    .unwrap_or_else(|| k1.value - self.tangent(i + 1) * (dt / 3.0));
+                let v = 1.0 - u;
+                k0.value * (v * v * v)
+                    + c1 * (3.0 * v * v * u)
+                    + c2 * (3.0 * v * u * u)
+                    + k1.value * (u * u * u)
+            }
+        }
+    }
+}
+
+// A single translation / rotation (Euler degrees) / scale pose.
+#[derive(Debug, Clone, Copy)]
+pub struct Trs {
+    pub translation: Vec3,
+    pub rotation: Vec3,
+    pub scale: Vec3,
+}
+impl Trs {
+    pub fn identity() -> Self {
+        Self::construct(
+            &Vec3::new(),
+            &Vec3::new(),
+            &Vec3::construct(&[1.0, 1.0, 1.0]),
+        )
+    }
+    pub fn construct(translation: &Vec3, rotation: &Vec3, scale: &Vec3) -> Self {
+        Self {
+            translation: *translation,
+            rotation: *rotation,
+            scale: *scale,
+        }
+    }
+}
+
+// Animated translation / rotation (Euler degrees) / scale.
+#[derive(Debug, Clone)]
+pub struct TransformTrack {
+    pub translation: Track<Vec3>,
+    pub rotation: Track<Vec3>,
+    pub scale: Track<Vec3>,
+}
+impl TransformTrack {
+    pub fn construct(translation: Track<Vec3>, rotation: Track<Vec3>, scale: Track<Vec3>) -> Self {
+        Self {
+            translation,
+            rotation,
+            scale,
+        }
+    }
+    pub fn translation_only(translation: Track<Vec3>) -> Self {
+        Self::construct(
+            translation,
+            Track::constant(Vec3::new()),
+            Track::constant(Vec3::construct(&[1.0, 1.0, 1.0])),
+        )
+    }
+    // Linear motion from `start` at time0 to `end` at time1.
+    pub fn between(time0: f64, start: &Trs, time1: f64, end: &Trs) -> Self {
+        let track = |a: Vec3, b: Vec3| {
+            Track::construct(
+                &[Keyframe::construct(time0, a), Keyframe::construct(time1, b)],
+                Interpolation::Linear,
+            )
+        };
+        Self::construct(
+            track(start.translation, end.translation),
+            track(start.rotation, end.rotation),
+            track(start.scale, end.scale),
+        )
+    }
+    pub fn key_times(&self) -> Vec<f64> {
+        let mut times: Vec<f64> = [&self.translation, &self.rotation, &self.scale]
+            .iter()
+            .flat_map(|t| t.keys.iter().map(|k| k.time))
+            .collect();
+        times.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        times.dedup();
+        times
+    }
+    pub fn at(&self, time: f64) -> Transform {
+        Transform::from_trs(
+            &self.translation.sample(time),
+            &self.rotation.sample(time),
+            &self.scale.sample(time),
+        )
+    }
+}
+
+// Animated eye point and target; everything else comes from a CameraBuilder.
+#[derive(Debug, Clone)]
+pub struct CameraTrack {
+    pub position: Track<Vec3>,
+    pub target: Track<Vec3>,
+    pub vfov: Option<Track<f64>>,
+}
+impl CameraTrack {
+    pub fn construct(position: Track<Vec3>, target: Track<Vec3>) -> Self {
+        Self {
+            position,
+            target,
+            vfov: None,
+        }
+    }
+    pub fn with_vfov(mut self, vfov: Track<f64>) -> Self {
+        self.vfov = Some(vfov);
+        self
+    }
+    // Camera for a frame starting at `time`, with the shutter open over
+    // [shutter_open, shutter_close].
+    pub fn camera_at(
+        &self,
+        builder: &CameraBuilder,
+        time: f64,
+        shutter_open: f64,
+        shutter_close: f64,
+    ) -> PerspectiveCamera {
+        let mut builder = builder
+            .clone()
+            .look_from(&self.position.sample(time))
+            .look_at(&self.target.sample(time))
+            .shutter(shutter_open, shutter_close);
+        if let Some(vfov) = &self.vfov {
+            builder = builder.vfov(vfov.sample(time));
+        }
+        builder.build()
+    }
+}
+
+// Cameras built across the shutter interval of an AnimatedCamera.
+const SHUTTER_STEPS: usize = 8;
+
+// Camera that keeps following a CameraTrack while the shutter is open, so
+// camera moves blur. The track is evaluated once per frame at evenly spaced
+// times; each ray interpolates between the two cameras around its time.
+#[derive(Clone)]
+pub struct AnimatedCamera {
+    pub cameras: Vec<PerspectiveCamera>,
+    pub time0: f64,
+    pub time1: f64,
+}
+impl AnimatedCamera {
+    pub fn construct(
+        track: &CameraTrack,
+        builder: &CameraBuilder,
+        shutter_open: f64,
+        shutter_close: f64,
+    ) -> Self {
+        let cameras = (0..=SHUTTER_STEPS)
+            .map(|i| {
+                let f = i as f64 / SHUTTER_STEPS as f64;
+                let time = shutter_open + f * (shutter_close - shutter_open);
+                track.camera_at(builder, time, time, time)
+            })
+            .collect();
+        Self {
+            cameras,
+            time0: shutter_open,
+            time1: shutter_close,
+        }
+    }
+    fn camera(&self) -> PerspectiveCamera {
+        let time = random_double_range(self.time0, self.time1);
+        let x = if self.time1 > self.time0 {
+            (time - self.time0) / (self.time1 - self.time0) * SHUTTER_STEPS as f64
+        } else {
+            0.0
+        };
+        let i = (x.floor() as usize).min(SHUTTER_STEPS - 1);
+        let f = x - i as f64;
+        let (a, b) = (&self.cameras[i], &self.cameras[i + 1]);
+        let lerp = |p: Vec3, q: Vec3| p * (1.0 - f) + q * f;
+        PerspectiveCamera {
+            origin: lerp(a.origin, b.origin),
+            lower_left_corner: lerp(a.lower_left_corner, b.lower_left_corner),
+            horizontal: lerp(a.horizontal, b.horizontal),
+            vertical: lerp(a.vertical, b.vertical),
+            u: lerp(a.u, b.u),
+            v: lerp(a.v, b.v),
+            w: lerp(a.w, b.w),
+            lens_radius: a.lens_radius * (1.0 - f) + b.lens_radius * f,
+            time0: time,
+            time1: time,
+            aperture: a.aperture.clone(),
+            cats_eye: a.cats_eye,
+        }
+    }
+}
+impl Camera for AnimatedCamera {
+    fn get_ray(&self, s: f64, t: f64) -> Option<Ray> {
+        self.camera().get_ray(s, t)
+    }
+    fn get_ray_differential(&self, s: f64, t: f64, ds: f64, dt: f64) -> Option<Ray> {
+        self.camera().get_ray_differential(s, t, ds, dt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> Vec<Keyframe<f64>> {
+        vec![
+            Keyframe::construct(0.0, 1.0),
+            Keyframe::construct(1.0, 4.0).with_handles(2.0, 7.0),
+            Keyframe::construct(2.5, -2.0),
+            Keyframe::construct(4.0, 3.0),
+        ]
+    }
+
+    #[test]
+    fn splines_pass_through_their_keys() {
+        for interpolation in [Interpolation::CatmullRom, Interpolation::Bezier].iter() {
+            let track = Track::construct(&keys(), *interpolation);
+            for k in keys() {
+                assert!((track.sample(k.time) - k.value).abs() < 1e-12);
+                // Continuous on both sides of the key.
+                assert!((track.sample(k.time - 1e-9) - k.value).abs() < 1e-6);
+                assert!((track.sample(k.time + 1e-9) - k.value).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn bezier_follows_its_handles() {
+        // A straight segment whose handles sit at the thirds is linear.
+        let track = Track::construct(
+            &[
+                Keyframe::construct(0.0, 0.0).with_handles(-1.0, 1.0),
+                Keyframe::construct(1.0, 3.0).with_handles(2.0, 4.0),
+            ],
+            Interpolation::Bezier,
+        );
+        for i in 0..=10 {
+            let t = i as f64 / 10.0;
+            assert!((track.sample(t) - 3.0 * t).abs() < 1e-12);
+        }
+    }
+}
@@ -1,9 +1,135 @@
-use crate::ray::Ray;
-use crate::rt_weekend::{degrees_to_radians, random_double_range};
-use crate::vec3::{cross, random_in_unit_disk, Point3, Vec3};
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::ray::{Ray, RayDifferential};
+use crate::rt_weekend::{degrees_to_radians, random_double, random_double_range, random_int, PI};
+use crate::vec3::{cross, dot, random_in_unit_disk, Point3, Vec3};
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+// Maps film coordinates (s, t) in [0, 1]^2, origin at the bottom left, to a
+// primary ray. None means the film point sees nothing, e.g. outside a
+// fisheye's image circle.
+pub trait Camera: Send + Sync {
+    fn get_ray(&self, s: f64, t: f64) -> Option<Ray>;
+    fn get_ray_differential(&self, s: f64, t: f64, ds: f64, dt: f64) -> Option<Ray> {
+        // Generic fallback: trace the neighbouring film points as well.
+        // Only exact for cameras whose get_ray is deterministic apart from time.
+        let r = self.get_ray(s, t)?;
+        let differential = match (self.get_ray(s + ds, t), self.get_ray(s, t + dt)) {
+            (Some(rx), Some(ry)) => Some(RayDifferential {
+                rx_origin: rx.origin(),
+                rx_direction: rx.direction(),
+                ry_origin: ry.origin(),
+                ry_direction: ry.direction(),
+            }),
+            _ => None,
+        };
+        Some(r.with_differential(differential))
+    }
+}
+
+// Orthonormal frame looking from `lookfrom` towards `lookat`; the camera
+// looks down -w.
+pub fn camera_basis(lookfrom: &Point3, lookat: &Point3, vup: &Vec3) -> (Vec3, Vec3, Vec3) {
+    let w: Vec3 = (*lookfrom - *lookat).unit();
+    let u: Vec3 = cross(vup, &w).unit();
+    let v: Vec3 = cross(&w, &u);
+    (u, v, w)
+}
+
+// Bokeh shape: an importance-sampled image of the aperture, drawn so that
+// brighter pixels let more light through.
+#[derive(Debug)]
+pub struct ApertureMask {
+    pub width: u32,
+    pub height: u32,
+    cdf: Vec<f64>,
+}
+impl ApertureMask {
+    pub fn construct(luminance: &[f64], width: u32, height: u32) -> Self {
+        assert_eq!(
+            luminance.len(),
+            (width * height) as usize,
+            "Aperture mask size mismatch."
+        );
+        let mut cdf = Vec::with_capacity(luminance.len());
+        let mut total = 0.0;
+        for l in luminance {
+            total += l.max(0.0);
+            cdf.push(total);
+        }
+        assert!(total > 0.0, "Aperture mask is completely opaque.");
+        for c in cdf.iter_mut() {
+            *c /= total;
+        }
+        Self { width, height, cdf }
+    }
+    pub fn from_path<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let img = image::open(path)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?
+            .into_luma8();
+        let (width, height) = img.dimensions();
+        let luminance: Vec<f64> = img.into_raw().iter().map(|b| *b as f64 / 255.0).collect();
+        Ok(Self::construct(&luminance, width, height))
+    }
+    fn sample(&self) -> (f64, f64) {
+        // Pick a pixel by luminance, jitter inside it, and map the image to
+        // [-1, 1]^2 with +y up.
+        let xi = random_double();
+        let index = match self.cdf.binary_search_by(|c| c.partial_cmp(&xi).unwrap()) {
+            Ok(i) => i,
+            Err(i) => i.min(self.cdf.len() - 1),
+        } as u32;
+        let i = index % self.width;
+        let j = index / self.width;
+        let x = (i as f64 + random_double()) / self.width as f64;
+        let y = (j as f64 + random_double()) / self.height as f64;
+        (2.0 * x - 1.0, 1.0 - 2.0 * y)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Aperture {
+    Disk,
+    // Regular polygon inscribed in the unit disk; rotation in degrees.
+    Polygon { blades: u32, rotation: f64 },
+    Mask(Arc<ApertureMask>),
+}
+impl Default for Aperture {
+    fn default() -> Self {
+        Aperture::Disk
+    }
+}
+impl Aperture {
+    // A point on the aperture in units of the lens radius.
+    pub fn sample(&self) -> (f64, f64) {
+        match self {
+            Aperture::Disk => {
+                let p = random_in_unit_disk();
+                (p.x(), p.y())
+            }
+            Aperture::Polygon { blades, rotation } => {
+                // Uniform point in one of the `blades` triangles fanning out
+                // from the center.
+                let k = random_int(0, blades - 1) as f64;
+                let step = 2.0 * PI / *blades as f64;
+                let a0 = degrees_to_radians(*rotation) + k * step;
+                let a1 = a0 + step;
+                let r1 = random_double().sqrt();
+                let r2 = random_double();
+                (
+                    r1 * ((1.0 - r2) * a0.cos() + r2 * a1.cos()),
+                    r1 * ((1.0 - r2) * a0.sin() + r2 * a1.sin()),
+                )
+            }
+            Aperture::Mask(mask) => mask.sample(),
+        }
+    }
+}
 
 #[derive(Clone, Default)]
-pub struct Camera {
+pub struct PerspectiveCamera {
     pub origin: Point3,
     pub lower_left_corner: Point3,
     pub horizontal: Vec3,
@@ -15,9 +141,13 @@ pub struct Camera {
     pub time0: f64,
     pub time1: f64,
     // Shutter open / close time
+    pub aperture: Aperture,
+    // Off-axis vignetting: how far, in lens radii at the frame corner, the
+    // barrel's clipping circle is shifted away from the aperture.
+    pub cats_eye: f64,
 }
 
-impl Camera {
+impl PerspectiveCamera {
     pub fn new(
         lookfrom: &Point3,
         lookat: &Point3,
@@ -36,10 +166,7 @@ impl Camera {
         let viewport_height: f64 = 2.0 * h;
         let viewport_width: f64 = aspect_ratio * viewport_height;
 
-        let scope: Vec3 = *lookfrom - *lookat;
-        let w: Vec3 = scope.unit();
-        let u: Vec3 = cross(vup, &w).unit();
-        let v: Vec3 = cross(&w, &u);
+        let (u, v, w) = camera_basis(lookfrom, lookat, vup);
 
         let origin = *lookfrom;
         let horizontal = focus_dist * viewport_width * u;
@@ -55,17 +182,451 @@ impl Camera {
             lens_radius: aperture / 2.0,
             time0,
             time1,
+            aperture: Aperture::Disk,
+            cats_eye: 0.0,
         }
     }
-    pub fn get_ray(&self, s: f64, t: f64) -> Ray {
-        let rd: Vec3 = self.lens_radius * random_in_unit_disk();
+}
+impl Camera for PerspectiveCamera {
+    fn get_ray(&self, s: f64, t: f64) -> Option<Ray> {
+        let (lx, ly) = self.aperture.sample();
+        if self.cats_eye > 0.0 {
+            let cx = self.cats_eye * (2.0 * s - 1.0);
+            let cy = self.cats_eye * (2.0 * t - 1.0);
+            if (lx - cx) * (lx - cx) + (ly - cy) * (ly - cy) > 1.0 {
+                return None;
+            }
+        }
+        let rd: Vec3 = self.lens_radius * Vec3::construct(&[lx, ly, 0.0]);
         let offset: Vec3 = self.u * rd.x() + self.v * rd.y();
-        Ray::construct(
+        Some(Ray::construct(
             &(self.origin + offset),
             &(self.lower_left_corner + self.horizontal * s + self.vertical * t
                 - self.origin
                 - offset),
             random_double_range(self.time0, self.time1),
-        )
+        ))
+    }
+    fn get_ray_differential(&self, s: f64, t: f64, ds: f64, dt: f64) -> Option<Ray> {
+        // The auxiliary rays share the main ray's lens sample, so they only
+        // differ by where they cross the focal plane.
+        let r = self.get_ray(s, t)?;
+        let origin = r.origin();
+        let differential = RayDifferential {
+            rx_origin: origin,
+            rx_direction: r.direction() + self.horizontal * ds,
+            ry_origin: origin,
+            ry_direction: r.direction() + self.vertical * dt,
+        };
+        Some(r.with_differential(Some(differential)))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Focus {
+    Distance(f64),
+    // Focus plane through this point.
+    Point(Point3),
+    // Whatever the center of the frame sees of this object, or its bounding
+    // box center if the center ray misses.
+    Object(Arc<dyn Hittable>),
+}
+
+// Typed construction of a PerspectiveCamera from physical lens settings.
+// Lengths on the lens side (focal length, sensor) are in millimetres;
+// `units_per_meter` relates them to scene units.
+#[derive(Debug, Clone)]
+pub struct CameraBuilder {
+    lookfrom: Point3,
+    lookat: Point3,
+    vup: Vec3,
+    aspect_ratio: f64,
+    vfov: f64,
+    focal_length: Option<f64>,
+    sensor_height: f64,
+    f_stop: Option<f64>,
+    aperture_diameter: f64,
+    units_per_meter: f64,
+    focus: Focus,
+    aperture: Aperture,
+    cats_eye: f64,
+    time0: f64,
+    time1: f64,
+}
+impl CameraBuilder {
+    pub fn new() -> Self {
+        Self {
+            lookfrom: Point3::construct(&[0.0, 0.0, 0.0]),
+            lookat: Point3::construct(&[0.0, 0.0, -1.0]),
+            vup: Vec3::construct(&[0.0, 1.0, 0.0]),
+            aspect_ratio: 1.0,
+            vfov: 40.0,
+            focal_length: None,
+            sensor_height: 24.0,
+            f_stop: None,
+            aperture_diameter: 0.0,
+            units_per_meter: 1.0,
+            focus: Focus::Distance(10.0),
+            aperture: Aperture::Disk,
+            cats_eye: 0.0,
+            time0: 0.0,
+            time1: 1.0,
+        }
+    }
+    pub fn look_from(mut self, lookfrom: &Point3) -> Self {
+        self.lookfrom = *lookfrom;
+        self
+    }
+    pub fn look_at(mut self, lookat: &Point3) -> Self {
+        self.lookat = *lookat;
+        self
+    }
+    pub fn vup(mut self, vup: &Vec3) -> Self {
+        self.vup = *vup;
+        self
+    }
+    pub fn aspect_ratio(mut self, aspect_ratio: f64) -> Self {
+        self.aspect_ratio = aspect_ratio;
+        self
+    }
+    // Vertical field of view in degrees; overridden by focal_length.
+    pub fn vfov(mut self, vfov: f64) -> Self {
+        self.vfov = vfov;
+        self.focal_length = None;
+        self
+    }
+    pub fn focal_length(mut self, mm: f64) -> Self {
+        self.focal_length = Some(mm);
+        self
+    }
+    pub fn sensor_height(mut self, mm: f64) -> Self {
+        self.sensor_height = mm;
+        self
+    }
+    // Aperture as focal length / f-number. Without a focal_length, the focal
+    // length is the one that gives vfov on this sensor.
+    pub fn f_stop(mut self, f_number: f64) -> Self {
+        self.f_stop = Some(f_number);
+        self
+    }
+    // Aperture diameter directly in scene units.
+    pub fn aperture_diameter(mut self, diameter: f64) -> Self {
+        self.aperture_diameter = diameter;
+        self.f_stop = None;
+        self
+    }
+    pub fn units_per_meter(mut self, units: f64) -> Self {
+        self.units_per_meter = units;
+        self
+    }
+    pub fn focus_distance(mut self, distance: f64) -> Self {
+        self.focus = Focus::Distance(distance);
+        self
+    }
+    pub fn focus_on_point(mut self, p: &Point3) -> Self {
+        self.focus = Focus::Point(*p);
+        self
+    }
+    pub fn focus_on_object(mut self, object: Arc<dyn Hittable>) -> Self {
+        self.focus = Focus::Object(object);
+        self
+    }
+    pub fn blades(mut self, blades: u32, rotation: f64) -> Self {
+        assert!(blades >= 3, "An aperture needs at least three blades.");
+        self.aperture = Aperture::Polygon { blades, rotation };
+        self
+    }
+    pub fn aperture_mask(mut self, mask: Arc<ApertureMask>) -> Self {
+        self.aperture = Aperture::Mask(mask);
+        self
+    }
+    pub fn cats_eye(mut self, strength: f64) -> Self {
+        self.cats_eye = strength;
+        self
+    }
+    pub fn shutter(mut self, time0: f64, time1: f64) -> Self {
+        self.time0 = time0;
+        self.time1 = time1;
+        self
+    }
+
+    fn focus_distance_to(&self, p: &Point3) -> f64 {
+        // Distance along the view axis, so the whole focal plane passes
+        // through p rather than a sphere around the camera.
+        let forward = (self.lookat - self.lookfrom).unit();
+        dot(&(*p - self.lookfrom), &forward)
+    }
+    fn resolve_focus(&self) -> f64 {
+        match &self.focus {
+            Focus::Distance(d) => *d,
+            Focus::Point(p) => self.focus_distance_to(p),
+            Focus::Object(object) => {
+                let r = Ray::construct(&self.lookfrom, &(self.lookat - self.lookfrom), self.time0);
+                let mut rec = HitRecord::new();
+                if object.hit(&r, 0.001, f64::INFINITY, &mut rec) {
+                    return self.focus_distance_to(&rec.p);
+                }
+                let mut bbox = Aabb::default();
+                if object.bounding_box(self.time0, self.time1, &mut bbox) {
+                    let center = 0.5 * (bbox.minimum() + bbox.maximum());
+                    return self.focus_distance_to(&center);
+                }
+                (self.lookat - self.lookfrom).length()
+            }
+        }
+    }
+    pub fn build(&self) -> PerspectiveCamera {
+        let vfov = match self.focal_length {
+            Some(f) => 2.0 * (self.sensor_height / (2.0 * f)).atan() * 180.0 / PI,
+            None => self.vfov,
+        };
+        let aperture = match self.f_stop {
+            Some(n) => {
+                let f = self.focal_length.unwrap_or_else(|| {
+                    self.sensor_height / (2.0 * degrees_to_radians(vfov / 2.0).tan())
+                });
+                f / n * 0.001 * self.units_per_meter
+            }
+            None => self.aperture_diameter,
+        };
+        let focus_dist = self.resolve_focus().max(1e-3);
+        let mut cam = PerspectiveCamera::new(
+            &self.lookfrom,
+            &self.lookat,
+            &self.vup,
+            &[vfov, self.aspect_ratio, aperture, focus_dist],
+            self.time0,
+            self.time1,
+        );
+        cam.aperture = self.aperture.clone();
+        cam.cats_eye = self.cats_eye;
+        cam
+    }
+}
+
+// Parallel projection: all rays share the view direction, for elevations
+// and other measured drawings.
+#[derive(Clone, Default)]
+pub struct OrthographicCamera {
+    pub lower_left_corner: Point3,
+    pub horizontal: Vec3,
+    pub vertical: Vec3,
+    pub w: Vec3,
+    pub time0: f64,
+    pub time1: f64,
+}
+impl OrthographicCamera {
+    pub fn new(
+        lookfrom: &Point3,
+        lookat: &Point3,
+        vup: &Vec3,
+        view_height: f64,
+        aspect_ratio: f64,
+        time0: f64,
+        time1: f64,
+    ) -> Self {
+        let (u, v, w) = camera_basis(lookfrom, lookat, vup);
+        let horizontal = aspect_ratio * view_height * u;
+        let vertical = view_height * v;
+        Self {
+            lower_left_corner: *lookfrom - horizontal / 2.0 - vertical / 2.0,
+            horizontal,
+            vertical,
+            w,
+            time0,
+            time1,
+        }
+    }
+}
+impl Camera for OrthographicCamera {
+    fn get_ray(&self, s: f64, t: f64) -> Option<Ray> {
+        Some(Ray::construct(
+            &(self.lower_left_corner + self.horizontal * s + self.vertical * t),
+            &(-self.w),
+            random_double_range(self.time0, self.time1),
+        ))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FisheyeProjection {
+    // Image radius proportional to the angle off axis.
+    Equidistant,
+    // Equal solid angle per unit of film area.
+    Equisolid,
+}
+
+// Circular fisheye; the image circle spans the shorter side of the film.
+#[derive(Clone)]
+pub struct FisheyeCamera {
+    pub origin: Point3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+    pub fov: f64, // radians, full angle across the image circle
+    pub aspect_ratio: f64,
+    pub projection: FisheyeProjection,
+    pub time0: f64,
+    pub time1: f64,
+}
+impl FisheyeCamera {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        lookfrom: &Point3,
+        lookat: &Point3,
+        vup: &Vec3,
+        fov: f64,
+        aspect_ratio: f64,
+        projection: FisheyeProjection,
+        time0: f64,
+        time1: f64,
+    ) -> Self {
+        let (u, v, w) = camera_basis(lookfrom, lookat, vup);
+        Self {
+            origin: *lookfrom,
+            u,
+            v,
+            w,
+            fov: degrees_to_radians(fov),
+            aspect_ratio,
+            projection,
+            time0,
+            time1,
+        }
+    }
+}
+impl Camera for FisheyeCamera {
+    fn get_ray(&self, s: f64, t: f64) -> Option<Ray> {
+        let x = (2.0 * s - 1.0) * self.aspect_ratio.max(1.0);
+        let y = (2.0 * t - 1.0) / self.aspect_ratio.min(1.0);
+        let r = (x * x + y * y).sqrt();
+        if r > 1.0 {
+            return None;
+        }
+        let theta = match self.projection {
+            FisheyeProjection::Equidistant => r * self.fov / 2.0,
+            FisheyeProjection::Equisolid => 2.0 * (r * (self.fov / 4.0).sin()).min(1.0).asin(),
+        };
+        let phi = y.atan2(x);
+        let direction =
+            theta.sin() * (phi.cos() * self.u + phi.sin() * self.v) - theta.cos() * self.w;
+        Some(Ray::construct(
+            &self.origin,
+            &direction,
+            random_double_range(self.time0, self.time1),
+        ))
+    }
+}
+
+// Full 360 x 180 degree latitude-longitude panorama, centered on lookat.
+#[derive(Clone)]
+pub struct EquirectangularCamera {
+    pub origin: Point3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+    pub time0: f64,
+    pub time1: f64,
+}
+impl EquirectangularCamera {
+    pub fn new(lookfrom: &Point3, lookat: &Point3, vup: &Vec3, time0: f64, time1: f64) -> Self {
+        let (u, v, w) = camera_basis(lookfrom, lookat, vup);
+        Self {
+            origin: *lookfrom,
+            u,
+            v,
+            w,
+            time0,
+            time1,
+        }
+    }
+    pub fn direction(&self, s: f64, t: f64) -> Vec3 {
+        let longitude = 2.0 * PI * (s - 0.5);
+        let latitude = PI * (t - 0.5);
+        latitude.cos() * (longitude.sin() * self.u - longitude.cos() * self.w)
+            + latitude.sin() * self.v
+    }
+}
+impl Camera for EquirectangularCamera {
+    fn get_ray(&self, s: f64, t: f64) -> Option<Ray> {
+        Some(Ray::construct(
+            &self.origin,
+            &self.direction(s, t),
+            random_double_range(self.time0, self.time1),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::sphere::Sphere;
+    use crate::vec3::Color3;
+
+    fn focus_center(cam: &PerspectiveCamera) -> Point3 {
+        cam.lower_left_corner + 0.5 * cam.horizontal + 0.5 * cam.vertical
+    }
+
+    #[test]
+    fn f_stop_uses_the_focal_length() {
+        let cam = CameraBuilder::new().focal_length(50.0).f_stop(2.0).build();
+        assert!((cam.lens_radius - 0.0125).abs() < 1e-12);
+        // The same field of view given as vfov implies the same lens.
+        let vfov = 2.0 * (24.0f64 / 100.0).atan() * 180.0 / PI;
+        let cam = CameraBuilder::new().f_stop(2.0).vfov(vfov).build();
+        assert!((cam.lens_radius - 0.0125).abs() < 1e-9);
+    }
+
+    #[test]
+    fn focus_on_point_and_object() {
+        let builder = CameraBuilder::new().aperture_diameter(0.5);
+        // Only the distance along the view axis counts.
+        let cam = builder
+            .clone()
+            .focus_on_point(&Point3::construct(&[3.0, 1.0, -6.0]))
+            .build();
+        assert!((focus_center(&cam) - Point3::construct(&[0.0, 0.0, -6.0])).length() < 1e-9);
+
+        let sphere = Arc::new(Sphere::construct(
+            &Point3::construct(&[0.0, 0.0, -5.0]),
+            1.0,
+            Arc::new(Lambertian::construct(&Color3::new())),
+        ));
+        let cam = builder.clone().focus_on_object(sphere).build();
+        assert!((focus_center(&cam) - Point3::construct(&[0.0, 0.0, -4.0])).length() < 1e-9);
+
+        // Off axis, the object's bounding box center is used instead.
+        let sphere = Arc::new(Sphere::construct(
+            &Point3::construct(&[5.0, 0.0, -8.0]),
+            1.0,
+            Arc::new(Lambertian::construct(&Color3::new())),
+        ));
+        let cam = builder.focus_on_object(sphere).build();
+        assert!((focus_center(&cam) - Point3::construct(&[0.0, 0.0, -8.0])).length() < 1e-9);
+    }
+
+    #[test]
+    fn polygon_aperture_samples_stay_inside() {
+        // Four blades from angle 0 make the square |x| + |y| <= 1.
+        let aperture = Aperture::Polygon {
+            blades: 4,
+            rotation: 0.0,
+        };
+        for _ in 0..10000 {
+            let (x, y) = aperture.sample();
+            assert!(x.abs() + y.abs() <= 1.0 + 1e-9);
+        }
+    }
+
+    #[test]
+    fn mask_aperture_samples_the_lit_pixels() {
+        // Only the top right pixel of a 2x2 mask lets light through.
+        let mask = ApertureMask::construct(&[0.0, 1.0, 0.0, 0.0], 2, 2);
+        let aperture = Aperture::Mask(Arc::new(mask));
+        for _ in 0..1000 {
+            let (x, y) = aperture.sample();
+            assert!((0.0..=1.0).contains(&x) && (0.0..=1.0).contains(&y));
+        }
     }
 }
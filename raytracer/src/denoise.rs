@@ -0,0 +1,193 @@
+use crate::film::{Aov, Film};
+use crate::vec3::{dot, Color3, Vec3};
+
+// Edge-avoiding A-Trous wavelet filter (Dammertz et al. 2010) with the
+// variance-driven luminance weight from SVGF (Schied et al. 2017). Each
+// pass applies a 5x5 B3-spline kernel with holes, doubling the step, so a
+// few passes cover a wide footprint cheaply.
+#[derive(Debug, Clone, Copy)]
+pub struct Denoiser {
+    pub iterations: u32,
+    // Luminance differences are measured in standard deviations of noise.
+    pub sigma_luminance: f64,
+    // Exponent on the cosine between normals.
+    pub sigma_normal: f64,
+    // Depth differences are measured against the local depth gradient.
+    pub sigma_depth: f64,
+    pub sigma_albedo: f64,
+}
+impl Denoiser {
+    // Feature buffers the film has to record for denoise().
+    pub const AOVS: [Aov; 3] = [Aov::Albedo, Aov::Normal, Aov::Depth];
+    const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+    const EPSILON: f64 = 1e-4;
+
+    pub fn new() -> Self {
+        Self {
+            iterations: 5,
+            sigma_luminance: 4.0,
+            sigma_normal: 128.0,
+            sigma_depth: 1.0,
+            sigma_albedo: 0.1,
+        }
+    }
+    pub fn with_iterations(mut self, iterations: u32) -> Self {
+        self.iterations = iterations;
+        self
+    }
+
+    fn demodulate(c: f64, albedo: f64) -> f64 {
+        if albedo > Self::EPSILON {
+            c / albedo
+        } else {
+            c
+        }
+    }
+    fn remodulate(c: f64, albedo: f64) -> f64 {
+        if albedo > Self::EPSILON {
+            c * albedo
+        } else {
+            c
+        }
+    }
+
+    // Denoised colour for every pixel, in film order.
+    pub fn denoise(&self, film: &Film) -> Vec<Color3> {
+        let (width, height) = (film.width as i32, film.height as i32);
+        let index = |i: i32, j: i32| (j * width + i) as usize;
+
+        // Filter the lighting alone, with texture divided out, so detail in
+        // the albedo survives however hard the lighting is smoothed.
+        let mut albedo = Vec::with_capacity((width * height) as usize);
+        let mut normal = Vec::with_capacity((width * height) as usize);
+        let mut depth = Vec::with_capacity((width * height) as usize);
+        let mut color = Vec::with_capacity((width * height) as usize);
+        let mut variance = Vec::with_capacity((width * height) as usize);
+        for j in 0..film.height {
+            for i in 0..film.width {
+                let a = film.aov(Aov::Albedo, i, j);
+                let c = film.color(i, j);
+                albedo.push(a);
+                normal.push(film.aov(Aov::Normal, i, j));
+                depth.push(film.aov(Aov::Depth, i, j).x());
+                color.push(Color3::construct(&[
+                    Self::demodulate(c.x(), a.x()),
+                    Self::demodulate(c.y(), a.y()),
+                    Self::demodulate(c.z(), a.z()),
+                ]));
+                let v = film.variance(i, j);
+                let v = if v.is_finite() { v } else { 0.0 };
+                variance.push(v / a.luminance().max(Self::EPSILON).powi(2));
+            }
+        }
+
+        let mut depth_gradient = Vec::with_capacity(depth.len());
+        for j in 0..height {
+            for i in 0..width {
+                let dx = depth[index((i + 1).min(width - 1), j)] - depth[index((i - 1).max(0), j)];
+                let dy = depth[index(i, (j + 1).min(height - 1))] - depth[index(i, (j - 1).max(0))];
+                depth_gradient.push((0.5 * dx.abs(), 0.5 * dy.abs()));
+            }
+        }
+
+        // Per-pixel variance from a handful of samples is itself noisy;
+        // a small blur makes the luminance weight much steadier.
+        let mut blurred = variance.clone();
+        for j in 0..height {
+            for i in 0..width {
+                let mut sum = 0.0;
+                let mut weight_sum = 0.0;
+                for dj in -1..=1 {
+                    for di in -1..=1 {
+                        let (qi, qj) = (i + di, j + dj);
+                        if qi < 0 || qi >= width || qj < 0 || qj >= height {
+                            continue;
+                        }
+                        let weight =
+                            if di == 0 { 0.5 } else { 0.25 } * if dj == 0 { 0.5 } else { 0.25 };
+                        sum += weight * variance[index(qi, qj)];
+                        weight_sum += weight;
+                    }
+                }
+                blurred[index(i, j)] = sum / weight_sum;
+            }
+        }
+        let mut variance = blurred;
+
+        for iteration in 0..self.iterations {
+            let step = 1 << iteration;
+            let mut next_color = color.clone();
+            let mut next_variance = variance.clone();
+            for j in 0..height {
+                for i in 0..width {
+                    let p = index(i, j);
+                    let luminance = color[p].luminance();
+                    let sigma_l = self.sigma_luminance * variance[p].sqrt() + Self::EPSILON;
+                    let (gx, gy) = depth_gradient[p];
+
+                    let mut color_sum = Color3::new();
+                    let mut variance_sum = 0.0;
+                    let mut weight_sum = 0.0;
+                    for (ky, hy) in Self::KERNEL.iter().enumerate() {
+                        for (kx, hx) in Self::KERNEL.iter().enumerate() {
+                            let dx = (kx as i32 - 2) * step;
+                            let dy = (ky as i32 - 2) * step;
+                            let (qi, qj) = (i + dx, j + dy);
+                            if qi < 0 || qi >= width || qj < 0 || qj >= height {
+                                continue;
+                            }
+                            let q = index(qi, qj);
+
+                            let w_l = (-(luminance - color[q].luminance()).abs() / sigma_l).exp();
+                            let w_n =
+                                Self::normal_weight(&normal[p], &normal[q], self.sigma_normal);
+                            let expected = gx * dx.abs() as f64 + gy * dy.abs() as f64;
+                            let w_z = (-(depth[p] - depth[q]).abs()
+                                / (self.sigma_depth * expected + Self::EPSILON))
+                                .exp();
+                            let w_a = (-(albedo[p] - albedo[q]).length_squared()
+                                / (self.sigma_albedo * self.sigma_albedo))
+                                .exp();
+                            let weight = hx * hy * w_l * w_n * w_z * w_a;
+
+                            color_sum += weight * color[q];
+                            variance_sum += weight * weight * variance[q];
+                            weight_sum += weight;
+                        }
+                    }
+                    // The centre tap always has weight, so weight_sum > 0.
+                    next_color[p] = color_sum / weight_sum;
+                    next_variance[p] = variance_sum / (weight_sum * weight_sum);
+                }
+            }
+            color = next_color;
+            variance = next_variance;
+        }
+
+        color
+            .iter()
+            .zip(albedo.iter())
+            .map(|(c, a)| {
+                Color3::construct(&[
+                    Self::remodulate(c.x(), a.x()),
+                    Self::remodulate(c.y(), a.y()),
+                    Self::remodulate(c.z(), a.z()),
+                ])
+            })
+            .collect()
+    }
+
+    fn normal_weight(n_p: &Vec3, n_q: &Vec3, sigma: f64) -> f64 {
+        // Pixels that saw only background have no normal; treat them alike.
+        if n_p.near_zero() || n_q.near_zero() {
+            return if n_p.near_zero() && n_q.near_zero() {
+                1.0
+            } else {
+                0.0
+            };
+        }
+        (dot(n_p, n_q) / (n_p.length() * n_q.length()))
+            .max(0.0)
+            .powf(sigma)
+    }
+}
@@ -0,0 +1,334 @@
+use crate::filter::Filter;
+use crate::vec3::{Color3, Point3, Vec3};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+// Film coordinates are in pixels with y pointing up: pixel (i, j) covers
+// [i, i + 1) x [j, j + 1) and its centre is at (i + 0.5, j + 0.5).
+
+// Arbitrary output variables a Film can record next to the beauty image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aov {
+    Albedo,
+    Normal,
+    Depth,
+    Position,
+    ObjectId,
+    MaterialId,
+    Direct,
+    Indirect,
+    Emission,
+    SampleCount,
+    Variance,
+}
+impl Aov {
+    pub const ALL: [Aov; 11] = [
+        Aov::Albedo,
+        Aov::Normal,
+        Aov::Depth,
+        Aov::Position,
+        Aov::ObjectId,
+        Aov::MaterialId,
+        Aov::Direct,
+        Aov::Indirect,
+        Aov::Emission,
+        Aov::SampleCount,
+        Aov::Variance,
+    ];
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+            Aov::Emission => "emission",
+            Aov::SampleCount => "sample_count",
+            Aov::Variance => "variance",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|aov| aov.name() == name)
+    }
+}
+
+// What one camera sample saw at its first hit. Emission is light leaving
+// that surface (or the background), direct is light reaching it straight
+// from an emitter, indirect is everything that bounced more than once.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SampleAovs {
+    pub albedo: Color3,
+    pub normal: Vec3,
+    pub depth: f64,
+    pub position: Point3,
+    pub object_id: u32,
+    pub material_id: u32,
+    pub emission: Color3,
+    pub direct: Color3,
+    pub indirect: Color3,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FilmPixel {
+    pub color_sum: Color3,
+    pub weight_sum: f64,
+    // Unfiltered statistics of the samples taken inside this pixel.
+    pub sample_count: u32,
+    pub luminance_sum: f64,
+    pub luminance_sq_sum: f64,
+}
+
+// AOVs are box filtered: averaging normals or IDs through a filter with
+// negative lobes would produce meaningless values.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AovPixel {
+    pub sum: SampleAovs,
+}
+impl AovPixel {
+    fn add(&mut self, sample: &SampleAovs) {
+        let sum = &mut self.sum;
+        sum.albedo += sample.albedo;
+        sum.normal += sample.normal;
+        sum.depth += sample.depth;
+        sum.position += sample.position;
+        sum.emission += sample.emission;
+        sum.direct += sample.direct;
+        sum.indirect += sample.indirect;
+        // IDs can't be averaged; keep the first one seen.
+        if sum.object_id == 0 {
+            sum.object_id = sample.object_id;
+        }
+        if sum.material_id == 0 {
+            sum.material_id = sample.material_id;
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Film {
+    pub width: u32,
+    pub height: u32,
+    pub filter: Arc<dyn Filter>,
+    pub pixels: Vec<FilmPixel>,
+    pub aovs: Vec<Aov>,
+    // Empty unless some AOV was requested.
+    pub aov_pixels: Vec<AovPixel>,
+}
+impl Film {
+    pub fn construct(width: u32, height: u32, filter: Arc<dyn Filter>) -> Self {
+        Self {
+            width,
+            height,
+            filter,
+            pixels: vec![FilmPixel::default(); (width * height) as usize],
+            aovs: Vec::new(),
+            aov_pixels: Vec::new(),
+        }
+    }
+    pub fn with_aovs(mut self, aovs: &[Aov]) -> Self {
+        self.aovs = aovs.to_vec();
+        self.aov_pixels = if aovs.is_empty() {
+            Vec::new()
+        } else {
+            vec![AovPixel::default(); (self.width * self.height) as usize]
+        };
+        self
+    }
+    // A tile for rendering pixels [x0, x1) x [y0, y1). It also covers the
+    // neighbours those samples splat into, so tiles can be filled on
+    // separate threads and merged afterwards.
+    pub fn tile(&self, x0: u32, y0: u32, x1: u32, y1: u32) -> FilmTile {
+        let margin = self.filter.radius().ceil() as i32;
+        let x0 = (x0 as i32 - margin).max(0);
+        let y0 = (y0 as i32 - margin).max(0);
+        let x1 = (x1 as i32 + margin).min(self.width as i32);
+        let y1 = (y1 as i32 + margin).min(self.height as i32);
+        let size = ((x1 - x0) * (y1 - y0)) as usize;
+        FilmTile {
+            x0,
+            y0,
+            x1,
+            y1,
+            filter: self.filter.clone(),
+            pixels: vec![FilmPixel::default(); size],
+            aov_pixels: if self.aov_pixels.is_empty() {
+                Vec::new()
+            } else {
+                vec![AovPixel::default(); size]
+            },
+        }
+    }
+    pub fn merge_tile(&mut self, tile: &FilmTile) {
+        for j in tile.y0..tile.y1 {
+            for i in tile.x0..tile.x1 {
+                let index = (j as u32 * self.width + i as u32) as usize;
+                let src = &tile.pixels[tile.index(i, j)];
+                let dst = &mut self.pixels[index];
+                dst.color_sum += src.color_sum;
+                dst.weight_sum += src.weight_sum;
+                dst.sample_count += src.sample_count;
+                dst.luminance_sum += src.luminance_sum;
+                dst.luminance_sq_sum += src.luminance_sq_sum;
+                if !tile.aov_pixels.is_empty() {
+                    self.aov_pixels[index].add(&tile.aov_pixels[tile.index(i, j)].sum);
+                }
+            }
+        }
+    }
+    pub fn color(&self, i: u32, j: u32) -> Color3 {
+        let pixel = &self.pixels[(j * self.width + i) as usize];
+        if pixel.weight_sum == 0.0 {
+            Color3::new()
+        } else {
+            pixel.color_sum / pixel.weight_sum
+        }
+    }
+    // Variance of the pixel's mean luminance, estimated from its samples.
+    pub fn variance(&self, i: u32, j: u32) -> f64 {
+        let pixel = &self.pixels[(j * self.width + i) as usize];
+        if pixel.sample_count < 2 {
+            return f64::INFINITY;
+        }
+        let n = pixel.sample_count as f64;
+        let mean = pixel.luminance_sum / n;
+        ((pixel.luminance_sq_sum / n - mean * mean) * n / (n - 1.0)).max(0.0) / n
+    }
+    // Standard error of the pixel as displayed, i.e. after the gamma 2 curve
+    // write_color applies, where d(sqrt L) = dL / (2 sqrt L). The slope is
+    // capped near black so dark pixels don't ask for endless samples.
+    pub fn error(&self, i: u32, j: u32) -> f64 {
+        let pixel = &self.pixels[(j * self.width + i) as usize];
+        let variance = self.variance(i, j);
+        if !variance.is_finite() {
+            return f64::INFINITY;
+        }
+        let mean = (pixel.luminance_sum / pixel.sample_count as f64).max(0.0);
+        variance.sqrt() / (2.0 * mean.sqrt()).max(0.1)
+    }
+    // Largest error over the pixel and its eight neighbours. A pixel whose
+    // few samples happen to agree is not trusted while the area around it
+    // is still noisy.
+    pub fn neighbourhood_error(&self, i: u32, j: u32) -> f64 {
+        let mut error: f64 = 0.0;
+        for nj in j.saturating_sub(1)..(j + 2).min(self.height) {
+            for ni in i.saturating_sub(1)..(i + 2).min(self.width) {
+                error = error.max(self.error(ni, nj));
+            }
+        }
+        error
+    }
+    pub fn aov(&self, aov: Aov, i: u32, j: u32) -> Color3 {
+        let index = (j * self.width + i) as usize;
+        let count = self.pixels[index].sample_count;
+        match aov {
+            Aov::SampleCount => return Color3::construct(&[count as f64; 3]),
+            Aov::Variance => {
+                let variance = self.variance(i, j);
+                let variance = if variance.is_finite() { variance } else { 0.0 };
+                return Color3::construct(&[variance; 3]);
+            }
+            _ => {}
+        }
+        if self.aov_pixels.is_empty() || count == 0 {
+            return Color3::new();
+        }
+        let sum = &self.aov_pixels[index].sum;
+        let n = count as f64;
+        match aov {
+            Aov::Albedo => sum.albedo / n,
+            Aov::Normal => sum.normal / n,
+            Aov::Depth => Color3::construct(&[sum.depth / n; 3]),
+            Aov::Position => sum.position / n,
+            Aov::ObjectId => Color3::construct(&[sum.object_id as f64; 3]),
+            Aov::MaterialId => Color3::construct(&[sum.material_id as f64; 3]),
+            Aov::Direct => sum.direct / n,
+            Aov::Indirect => sum.indirect / n,
+            Aov::Emission => sum.emission / n,
+            Aov::SampleCount | Aov::Variance => unreachable!(),
+        }
+    }
+    pub fn write_aov(&self, aov: Aov, path: &Path) -> io::Result<()> {
+        write_pfm(path, self.width, self.height, |i, j| self.aov(aov, i, j))
+    }
+}
+
+// Writes a colour PFM. Rows go bottom to top, which is also film order.
+pub fn write_pfm<F: Fn(u32, u32) -> Color3>(
+    path: &Path,
+    width: u32,
+    height: u32,
+    pixel: F,
+) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    // A negative scale marks the data as little-endian.
+    write!(out, "PF\n{} {}\n-1.0\n", width, height)?;
+    for j in 0..height {
+        for i in 0..width {
+            for c in pixel(i, j).e.iter() {
+                out.write_all(&(*c as f32).to_le_bytes())?;
+            }
+        }
+    }
+    out.flush()
+}
+
+#[derive(Debug)]
+pub struct FilmTile {
+    pub x0: i32,
+    pub y0: i32,
+    pub x1: i32,
+    pub y1: i32,
+    pub filter: Arc<dyn Filter>,
+    pub pixels: Vec<FilmPixel>,
+    pub aov_pixels: Vec<AovPixel>,
+}
+impl FilmTile {
+    fn index(&self, i: i32, j: i32) -> usize {
+        ((j - self.y0) * (self.x1 - self.x0) + (i - self.x0)) as usize
+    }
+    // Splats a sample taken at film position (x, y) into every pixel whose
+    // filter footprint contains it.
+    pub fn add_sample(&mut self, x: f64, y: f64, color: &Color3, aovs: Option<&SampleAovs>) {
+        // A NaN would poison every pixel it lands in; count it as black.
+        let mut color = *color;
+        for c in color.e.iter_mut() {
+            if c.is_nan() {
+                *c = 0.0;
+            }
+        }
+        let radius = self.filter.radius();
+        let i0 = ((x - 0.5 - radius).ceil() as i32).max(self.x0);
+        let i1 = ((x - 0.5 + radius).floor() as i32).min(self.x1 - 1);
+        let j0 = ((y - 0.5 - radius).ceil() as i32).max(self.y0);
+        let j1 = ((y - 0.5 + radius).floor() as i32).min(self.y1 - 1);
+        for j in j0..=j1 {
+            for i in i0..=i1 {
+                let weight = self.filter.evaluate(i as f64 + 0.5 - x, j as f64 + 0.5 - y);
+                if weight == 0.0 {
+                    continue;
+                }
+                let index = self.index(i, j);
+                let pixel = &mut self.pixels[index];
+                pixel.color_sum += weight * color;
+                pixel.weight_sum += weight;
+            }
+        }
+
+        let index = self.index(x.floor() as i32, y.floor() as i32);
+        let luminance = color.luminance();
+        let pixel = &mut self.pixels[index];
+        pixel.sample_count += 1;
+        pixel.luminance_sum += luminance;
+        pixel.luminance_sq_sum += luminance * luminance;
+        if let Some(aovs) = aovs {
+            if !self.aov_pixels.is_empty() {
+                self.aov_pixels[index].add(aovs);
+            }
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use crate::rt_weekend::PI;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+// Pixel reconstruction filter. Offsets are in pixels from the pixel centre;
+// the filter is zero outside [-radius, radius] on both axes.
+pub trait Filter: Debug + Send + Sync {
+    fn radius(&self) -> f64;
+    fn evaluate(&self, x: f64, y: f64) -> f64;
+}
+
+pub fn filter_by_name(name: &str, radius: Option<f64>) -> Option<Arc<dyn Filter>> {
+    let filter: Arc<dyn Filter> = match name {
+        "box" => Arc::new(BoxFilter::construct(radius.unwrap_or(0.5))),
+        "gaussian" => Arc::new(GaussianFilter::construct(radius.unwrap_or(1.5), 2.0)),
+        "mitchell" => Arc::new(MitchellFilter::construct(
+            radius.unwrap_or(2.0),
+            1.0 / 3.0,
+            1.0 / 3.0,
+        )),
+        "lanczos" => Arc::new(LanczosFilter::construct(radius.unwrap_or(3.0), 3.0)),
+        "blackman-harris" => Arc::new(BlackmanHarrisFilter::construct(radius.unwrap_or(2.0))),
+        _ => return None,
+    };
+    Some(filter)
+}
+
+// Equal weight over the pixel; radius 0.5 is the plain per-pixel average.
+#[derive(Debug, Clone, Copy)]
+pub struct BoxFilter {
+    pub radius: f64,
+}
+impl BoxFilter {
+    pub fn construct(radius: f64) -> Self {
+        Self { radius }
+    }
+}
+impl Filter for BoxFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        if x.abs() <= self.radius && y.abs() <= self.radius {
+            1.0
+        } else {
+            0.0
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GaussianFilter {
+    pub radius: f64,
+    pub alpha: f64,
+}
+impl GaussianFilter {
+    pub fn construct(radius: f64, alpha: f64) -> Self {
+        Self { radius, alpha }
+    }
+    fn gaussian(&self, d: f64) -> f64 {
+        // Shifted down so it reaches zero at the radius instead of cutting off.
+        ((-self.alpha * d * d).exp() - (-self.alpha * self.radius * self.radius).exp()).max(0.0)
+    }
+}
+impl Filter for GaussianFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.gaussian(x) * self.gaussian(y)
+    }
+}
+
+// Mitchell-Netravali cubic; B = C = 1/3 is the recommended balance between
+// blurring and ringing.
+#[derive(Debug, Clone, Copy)]
+pub struct MitchellFilter {
+    pub radius: f64,
+    pub b: f64,
+    pub c: f64,
+}
+impl MitchellFilter {
+    pub fn construct(radius: f64, b: f64, c: f64) -> Self {
+        Self { radius, b, c }
+    }
+    fn mitchell(&self, x: f64) -> f64 {
+        // The cubic is defined over [-2, 2]; stretch it to the radius.
+        let x = (2.0 * x / self.radius).abs();
+        let (b, c) = (self.b, self.c);
+        if x > 2.0 {
+            0.0
+        } else if x > 1.0 {
+            ((-b - 6.0 * c) * x * x * x
+                + (6.0 * b + 30.0 * c) * x * x
+                + (-12.0 * b - 48.0 * c) * x
+                + (8.0 * b + 24.0 * c))
+                / 6.0
+        } else {
+            ((12.0 - 9.0 * b - 6.0 * c) * x * x * x
+                + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                + (6.0 - 2.0 * b))
+                / 6.0
+        }
+    }
+}
+impl Filter for MitchellFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.mitchell(x) * self.mitchell(y)
+    }
+}
+
+// Sinc windowed by a wider sinc with `tau` lobes.
+#[derive(Debug, Clone, Copy)]
+pub struct LanczosFilter {
+    pub radius: f64,
+    pub tau: f64,
+}
+impl LanczosFilter {
+    pub fn construct(radius: f64, tau: f64) -> Self {
+        Self { radius, tau }
+    }
+    fn sinc(x: f64) -> f64 {
+        if x.abs() < 1e-5 {
+            1.0
+        } else {
+            (PI * x).sin() / (PI * x)
+        }
+    }
+    fn windowed_sinc(&self, x: f64) -> f64 {
+        if x.abs() > self.radius {
+            0.0
+        } else {
+            Self::sinc(x) * Self::sinc(x / self.tau)
+        }
+    }
+}
+impl Filter for LanczosFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.windowed_sinc(x) * self.windowed_sinc(y)
+    }
+}
+
+// Four-term Blackman-Harris window; nearly Gaussian but with compact support
+// and very low side lobes.
+#[derive(Debug, Clone, Copy)]
+pub struct BlackmanHarrisFilter {
+    pub radius: f64,
+}
+impl BlackmanHarrisFilter {
+    pub fn construct(radius: f64) -> Self {
+        Self { radius }
+    }
+    fn window(&self, x: f64) -> f64 {
+        if x.abs() > self.radius {
+            return 0.0;
+        }
+        let n = 0.5 * (x / self.radius + 1.0);
+        0.35875 - 0.48829 * (2.0 * PI * n).cos() + 0.14128 * (4.0 * PI * n).cos()
+            - 0.01168 * (6.0 * PI * n).cos()
+    }
+}
+impl Filter for BlackmanHarrisFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.window(x) * self.window(y)
+    }
+}
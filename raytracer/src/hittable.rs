@@ -1,9 +1,11 @@
 use crate::aabb::Aabb;
 use crate::material::Material;
-use crate::ray::Ray;
-use crate::rt_weekend::{degrees_to_radians, INFINITY};
+use crate::onb::Onb;
+use crate::ray::{Ray, RayDifferential};
+use crate::rt_weekend::{degrees_to_radians, hash_double, INFINITY};
+use crate::texture::Texture;
 use crate::vec3::Point3;
-use crate::vec3::{dot, Vec3};
+use crate::vec3::{cross, dot, reflect, refract, Vec3};
 use std::fmt::Debug;
 use std::sync::Arc;
 
@@ -11,11 +13,30 @@ use std::sync::Arc;
 pub struct HitRecord {
     pub p: Point3,
     pub normal: Vec3,
+    // Shading frame; starts as the geometric frame and may be perturbed by materials.
+    pub shading_normal: Vec3,
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
+    // Partial derivatives of p with respect to the surface parameters.
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
+    // Screen-space footprint, filled in by compute_differentials().
+    pub dpdx: Vec3,
+    pub dpdy: Vec3,
+    pub dudx: f64,
+    pub dudy: f64,
+    pub dvdx: f64,
+    pub dvdy: f64,
     pub mat_ptr: Option<Arc<dyn Material>>,
     pub t: f64,
     pub u: f64,
     pub v: f64,
     pub front_face: bool,
+    // Time of the incoming ray, for textures animated over the shutter.
+    pub time: f64,
+    // 1-based index of the object in the outermost HittableList that was hit.
+    // Objects inside a BVH or a nested list report the ID of that wrapper.
+    pub object_id: u32,
 }
 
 impl HitRecord {
@@ -23,11 +44,24 @@ impl HitRecord {
         Self {
             p: Point3::new(),
             normal: Vec3::new(),
+            shading_normal: Vec3::new(),
+            tangent: Vec3::new(),
+            bitangent: Vec3::new(),
+            dpdu: Vec3::new(),
+            dpdv: Vec3::new(),
+            dpdx: Vec3::new(),
+            dpdy: Vec3::new(),
+            dudx: 0.0,
+            dudy: 0.0,
+            dvdx: 0.0,
+            dvdy: 0.0,
             mat_ptr: None,
             t: 0.0,
             u: 0.0,
             v: 0.0,
             front_face: false,
+            time: 0.0,
+            object_id: 0,
         }
     }
     // pub fn construct(p: &Point3, normal: &Vec3, t: f64, front_face: bool) -> Self {
@@ -40,23 +74,148 @@ impl HitRecord {
     // }
     pub fn set_face_normal(&mut self, r: &Ray, outward_normal: &Vec3) {
         self.front_face = dot(&r.direction(), outward_normal) < 0.0;
+        self.time = r.time();
         self.normal = if self.front_face {
             *outward_normal
         } else {
             -*outward_normal
         };
+        self.shading_normal = self.normal;
+        let uvw = Onb::build_from_w(&self.normal);
+        self.tangent = uvw.u();
+        self.orient_bitangent();
+    }
+    pub fn set_tangent(&mut self, dpdu: &Vec3) {
+        // Gram-Schmidt against the shading normal; keeps the default frame when
+        // dpdu is degenerate (e.g. at the poles of a sphere).
+        let tangent = *dpdu - dot(dpdu, &self.shading_normal) * self.shading_normal;
+        if tangent.near_zero() {
+            return;
+        }
+        self.tangent = tangent.unit();
+        self.orient_bitangent();
+    }
+    // The bitangent completes the frame on the side dpdv points to, so it
+    // follows increasing v on back faces and mirrored uv layouts too.
+    fn orient_bitangent(&mut self) {
+        let bitangent = cross(&self.shading_normal, &self.tangent);
+        self.bitangent = if dot(&bitangent, &self.dpdv) < 0.0 {
+            -bitangent
+        } else {
+            bitangent
+        };
+    }
+    pub fn set_uv_derivatives(&mut self, dpdu: &Vec3, dpdv: &Vec3) {
+        self.dpdu = *dpdu;
+        self.dpdv = *dpdv;
+        self.set_tangent(dpdu);
+    }
+    pub fn compute_differentials(&mut self, r: &Ray) {
+        // Intersect the auxiliary rays with the tangent plane at p, then solve
+        // dp = dpdu * du + dpdv * dv on the two best-conditioned axes.
+        self.dpdx = Vec3::new();
+        self.dpdy = Vec3::new();
+        self.dudx = 0.0;
+        self.dudy = 0.0;
+        self.dvdx = 0.0;
+        self.dvdy = 0.0;
+        let rd = match r.differential() {
+            Some(rd) => rd,
+            None => return,
+        };
+        let n = self.normal;
+        let d = dot(&n, &self.p);
+        let denom_x = dot(&n, &rd.rx_direction);
+        let denom_y = dot(&n, &rd.ry_direction);
+        if denom_x.abs() < 1e-12 || denom_y.abs() < 1e-12 {
+            return;
+        }
+        let tx = (d - dot(&n, &rd.rx_origin)) / denom_x;
+        let ty = (d - dot(&n, &rd.ry_origin)) / denom_y;
+        self.dpdx = rd.rx_origin + tx * rd.rx_direction - self.p;
+        self.dpdy = rd.ry_origin + ty * rd.ry_direction - self.p;
+
+        let (a0, a1) = if n.x().abs() > n.y().abs() && n.x().abs() > n.z().abs() {
+            (1, 2)
+        } else if n.y().abs() > n.z().abs() {
+            (0, 2)
+        } else {
+            (0, 1)
+        };
+        let m = [
+            [self.dpdu.e[a0], self.dpdv.e[a0]],
+            [self.dpdu.e[a1], self.dpdv.e[a1]],
+        ];
+        let det = m[0][0] * m[1][1] - m[0][1] * m[1][0];
+        if det.abs() < 1e-12 {
+            return;
+        }
+        let solve = |b0: f64, b1: f64| {
+            (
+                (m[1][1] * b0 - m[0][1] * b1) / det,
+                (m[0][0] * b1 - m[1][0] * b0) / det,
+            )
+        };
+        let (dudx, dvdx) = solve(self.dpdx.e[a0], self.dpdx.e[a1]);
+        let (dudy, dvdy) = solve(self.dpdy.e[a0], self.dpdy.e[a1]);
+        self.dudx = dudx;
+        self.dvdx = dvdx;
+        self.dudy = dudy;
+        self.dvdy = dvdy;
+    }
+    pub fn reflect_differential(&self, r_in: &Ray) -> Option<RayDifferential> {
+        // Treats the surface as locally flat, i.e. ignores dn/dx and dn/dy.
+        r_in.differential().map(|rd| RayDifferential {
+            rx_origin: self.p + self.dpdx,
+            rx_direction: reflect(&rd.rx_direction.unit(), &self.shading_normal),
+            ry_origin: self.p + self.dpdy,
+            ry_direction: reflect(&rd.ry_direction.unit(), &self.shading_normal),
+        })
+    }
+    pub fn refract_differential(&self, r_in: &Ray, etai_over_etat: f64) -> Option<RayDifferential> {
+        r_in.differential().map(|rd| RayDifferential {
+            rx_origin: self.p + self.dpdx,
+            rx_direction: refract(
+                &rd.rx_direction.unit(),
+                &self.shading_normal,
+                etai_over_etat,
+            ),
+            ry_origin: self.p + self.dpdy,
+            ry_direction: refract(
+                &rd.ry_direction.unit(),
+                &self.shading_normal,
+                etai_over_etat,
+            ),
+        })
+    }
+    pub fn set_shading_normal(&mut self, n: &Vec3) {
+        self.shading_normal = n.unit();
+        let tangent = self.tangent;
+        self.tangent = Onb::build_from_w(&self.shading_normal).u();
+        self.set_tangent(&tangent);
     }
 }
 
 pub trait Hittable: Debug + Send + Sync {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool;
     fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut Aabb) -> bool;
-    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
+    // `time` is the shading time, so moving lights are sampled where they are.
+    fn pdf_value(&self, o: &Point3, v: &Vec3, _time: f64) -> f64 {
         0.0
     }
-    fn random(&self, o: &Vec3) -> Vec3 {
+    fn random(&self, o: &Vec3, _time: f64) -> Vec3 {
         Vec3::construct(&[1.0, 0.0, 0.0])
     }
+    // Fraction of light getting through along r between t_min and t_max, for
+    // shadow rays. Anything that is hit blocks it; media override this.
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64) -> f64 {
+        let mut rec = HitRecord::new();
+        if self.hit(r, t_min, t_max, &mut rec) {
+            0.0
+        } else {
+            1.0
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -79,8 +238,16 @@ impl Hittable for Translate {
             return false;
         }
         rec.p += self.offset;
-        let norm = rec.normal;
+        // rec.normal already faces the ray; recover the outward normal so that
+        // front_face survives the transform.
+        let norm = if rec.front_face {
+            rec.normal
+        } else {
+            -rec.normal
+        };
+        let tangent = rec.tangent;
         rec.set_face_normal(&moved_r, &norm);
+        rec.set_tangent(&tangent);
         true
     }
     fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut Aabb) -> bool {
@@ -162,6 +329,9 @@ impl Hittable for RotateY {
 
         let mut p = rec.p;
         let mut normal = rec.normal;
+        let mut tangent = rec.tangent;
+        let mut dpdu = rec.dpdu;
+        let mut dpdv = rec.dpdv;
 
         p.e[0] = self.cos_theta * rec.p.e[0] + self.sin_theta * rec.p.e[2];
         p.e[2] = -self.sin_theta * rec.p.e[0] + self.cos_theta * rec.p.e[2];
@@ -169,8 +339,22 @@ impl Hittable for RotateY {
         normal.e[0] = self.cos_theta * rec.normal.e[0] + self.sin_theta * rec.normal.e[2];
         normal.e[2] = -self.sin_theta * rec.normal.e[0] + self.cos_theta * rec.normal.e[2];
 
+        tangent.e[0] = self.cos_theta * rec.tangent.e[0] + self.sin_theta * rec.tangent.e[2];
+        tangent.e[2] = -self.sin_theta * rec.tangent.e[0] + self.cos_theta * rec.tangent.e[2];
+
+        dpdu.e[0] = self.cos_theta * rec.dpdu.e[0] + self.sin_theta * rec.dpdu.e[2];
+        dpdu.e[2] = -self.sin_theta * rec.dpdu.e[0] + self.cos_theta * rec.dpdu.e[2];
+        dpdv.e[0] = self.cos_theta * rec.dpdv.e[0] + self.sin_theta * rec.dpdv.e[2];
+        dpdv.e[2] = -self.sin_theta * rec.dpdv.e[0] + self.cos_theta * rec.dpdv.e[2];
+        rec.dpdu = dpdu;
+        rec.dpdv = dpdv;
+
+        if !rec.front_face {
+            normal = -normal;
+        }
         rec.p = p;
-        rec.set_face_normal(&rotated_r, &normal);
+        rec.set_face_normal(r, &normal);
+        rec.set_tangent(&tangent);
 
         true
     }
@@ -203,3 +387,66 @@ impl Hittable for FlipFace {
         self.ptr.bounding_box(time0, time1, output_box)
     }
 }
+
+#[derive(Debug, Clone, Copy)]
+pub enum AlphaMode {
+    // Keep the hit when alpha >= threshold.
+    Threshold(f64),
+    // Keep the hit with probability alpha.
+    Stochastic,
+}
+
+#[derive(Debug)]
+pub struct AlphaMask {
+    ptr: Arc<dyn Hittable>,
+    alpha: Arc<dyn Texture>,
+    mode: AlphaMode,
+}
+impl AlphaMask {
+    pub fn construct(p: Arc<dyn Hittable>, alpha: Arc<dyn Texture>, mode: AlphaMode) -> Self {
+        Self {
+            ptr: Arc::clone(&p),
+            alpha: Arc::clone(&alpha),
+            mode,
+        }
+    }
+    fn opaque_at(&self, r: &Ray, rec: &HitRecord) -> bool {
+        let alpha = self.alpha.value_at(rec).luminance();
+        match self.mode {
+            AlphaMode::Threshold(threshold) => alpha >= threshold,
+            AlphaMode::Stochastic => {
+                // Hashed rather than random, so re-tracing the same ray
+                // (e.g. when evaluating a light pdf) sees the same surface.
+                let o = r.origin();
+                let d = r.direction();
+                hash_double(&[o.x(), o.y(), o.z(), d.x(), d.y(), d.z(), rec.t]) < alpha
+            }
+        }
+    }
+}
+impl Hittable for AlphaMask {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let mut t_start = t_min;
+        loop {
+            if !self.ptr.hit(r, t_start, t_max, rec) {
+                return false;
+            }
+            if self.opaque_at(r, rec) {
+                return true;
+            }
+            t_start = rec.t + 0.0001;
+        }
+    }
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut Aabb) -> bool {
+        self.ptr.bounding_box(time0, time1, output_box)
+    }
+    // Light sampling picks points over the whole underlying shape, cut-out
+    // texels included, so the density has to be the shape's as well; a
+    // direction through a hole still has that probability of being drawn.
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f64) -> f64 {
+        self.ptr.pdf_value(o, v, time)
+    }
+    fn random(&self, o: &Vec3, time: f64) -> Vec3 {
+        self.ptr.random(o, time)
+    }
+}
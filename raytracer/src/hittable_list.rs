@@ -1,8 +1,8 @@
 // use crate::vec3::Point3;
 use crate::aabb::Aabb;
 use crate::hittable;
-use crate::vec3::{Point3, Vec3};
 use crate::ray::Ray;
+use crate::vec3::{Point3, Vec3};
 
 use hittable::{HitRecord, Hittable};
 use std::sync::Arc;
@@ -40,15 +40,29 @@ impl Hittable for HittableList {
         let mut temp_rec: HitRecord = HitRecord::new();
         let mut hit_anything: bool = false;
         let mut closest_so_far: f64 = t_max;
-        for object in self.objects.iter() {
+        for (index, object) in self.objects.iter().enumerate() {
             if object.hit(r, t_min, closest_so_far, &mut temp_rec) {
                 hit_anything = true;
                 closest_so_far = temp_rec.t;
                 *rec = temp_rec.clone();
+                // Nested lists overwrite this on the way out, so the ID is
+                // the index in the outermost list: everything under a BVH or
+                // an inner list shares the ID of that wrapper.
+                rec.object_id = index as u32 + 1;
             }
         }
         hit_anything
     }
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64) -> f64 {
+        let mut tr = 1.0;
+        for object in &self.objects {
+            tr *= object.transmittance(r, t_min, t_max);
+            if tr == 0.0 {
+                break;
+            }
+        }
+        tr
+    }
     fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut Aabb) -> bool {
         if self.objects.is_empty() {
             return false;
@@ -70,16 +84,16 @@ impl Hittable for HittableList {
         }
         true
     }
-    fn pdf_value(&self, o: &crate::vec3::Point3, v: &crate::vec3::Vec3) -> f64 {
+    fn pdf_value(&self, o: &crate::vec3::Point3, v: &crate::vec3::Vec3, time: f64) -> f64 {
         let weight = 1.0 / self.objects.len() as f64;
         let mut sum = 0.0;
         for object in &self.objects {
-            sum += weight * object.pdf_value(o, v);
+            sum += weight * object.pdf_value(o, v, time);
         }
         sum
     }
-    fn random(&self, o: &crate::vec3::Vec3) -> crate::vec3::Vec3 {
+    fn random(&self, o: &crate::vec3::Vec3, time: f64) -> crate::vec3::Vec3 {
         let int_size = self.objects.len();
-        self.objects[rand::random::<usize>() % int_size].random(o)
+        self.objects[rand::random::<usize>() % int_size].random(o, time)
     }
 }
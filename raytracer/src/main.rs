@@ -1,15 +1,20 @@
 use console::style;
 use image::{ImageBuffer, RgbImage};
 use indicatif::ProgressBar;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::{fs::File, process::exit};
 
 mod aabb;
 mod aarect;
+mod animation;
 mod boxes;
 mod bvh;
 mod camera;
 mod constant_medium;
+mod denoise;
+mod film;
+mod filter;
 mod hittable;
 mod hittable_list;
 mod material;
@@ -18,31 +23,47 @@ mod onb;
 mod pdf;
 mod perlin;
 mod ray;
+mod realistic_camera;
 mod rt_weekend;
+mod spectrum;
 mod sphere;
+mod stereo;
+mod subsurface;
 mod texture;
+mod texture_ops;
+mod transform;
 mod vec3;
+mod volume;
 
 use aarect::{XyRect, XzRect, YzRect};
+use animation::{AnimatedCamera, CameraTrack, Interpolation, Keyframe, Track, TransformTrack};
 use boxes::Box;
-use camera::Camera;
+use camera::{Camera, CameraBuilder};
+use denoise::Denoiser;
+use film::{Aov, Film, SampleAovs};
+use filter::Filter;
 use pdf::{HittablePdf, MixturePdf, Pdf};
 // use constant_medium::ConstantMedium;
 use hittable::{FlipFace, HitRecord, Hittable, RotateY, Translate};
 use hittable_list::HittableList;
-// use image::GenericImageView;
 //use material::DiffuseLight;
 // use material::{Dielectric, Lambertian, Material, Metal};
 use material::{DiffuseLight, Lambertian, Material, Metal, ScatterRecord};
 // use moving_sphere::MovingSphere;
+use perlin::Perlin;
 use ray::Ray;
 use rt_weekend::random_double;
+use spectrum::{SpectralSample, Wavelengths, HERO_COUNT};
+use transform::MotionTransform;
 // use sphere::Sphere;
-// use texture::{CheckerTexture, ImageTexture, NoiseTexture, Texture};
+// use texture::{CheckerTexture, ColorSpace, ImageTexture, NoiseTexture, Texture};
 use vec3::{Color3, Point3, Vec3};
+use volume::{DensityGrid, GridVolume};
 
-use std::sync::mpsc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
 use std::thread;
+use std::time::Instant;
 
 use crate::material::Dielectric;
 use crate::sphere::Sphere;
@@ -66,14 +87,20 @@ pub fn ray_color(
     world: &dyn Hittable,
     lights: Arc<dyn Hittable>,
     depth: i32,
+    mut aov: Option<&mut SampleAovs>,
 ) -> Color3 {
     let mut rec: HitRecord = HitRecord::new();
     if depth <= 0 {
         return Color3::new();
     }
     if !world.hit(r, 0.001, f64::INFINITY, &mut rec) {
+        if let Some(aov) = aov {
+            aov.albedo = *background;
+            aov.emission = *background;
+        }
         return *background;
     }
+    rec.compute_differentials(r);
 
     let mut srec = ScatterRecord::new();
     // let attenuation: Color3 = Color3::new();
@@ -82,16 +109,79 @@ pub fn ray_color(
         .as_ref()
         .unwrap()
         .emitted(&r, &rec, rec.u, rec.v, &rec.p);
-    if !rec.mat_ptr.as_ref().unwrap().scatter(r, &rec, &mut srec) {
+    let scatters = rec.mat_ptr.as_ref().unwrap().scatter(r, &rec, &mut srec);
+    if let Some(aov) = aov.as_deref_mut() {
+        aov.albedo = if scatters { srec.attenuation } else { emitted };
+        aov.normal = rec.shading_normal;
+        aov.depth = rec.t * r.direction().length();
+        aov.position = rec.p;
+        aov.object_id = rec.object_id;
+        aov.material_id = rec.mat_ptr.as_ref().unwrap().id();
+        aov.emission = emitted;
+    }
+    if !scatters {
         return emitted;
     }
 
+    if srec.is_medium {
+        // Light is sampled explicitly through the medium, so the phase-sampled
+        // path must know what emission it hits to weight it against that.
+        let direct = sample_light_through_media(r, &rec, &srec, world, &lights);
+        let phase = srec.pdf_ptr.as_ref().unwrap();
+        let scattered = Ray::construct(&rec.p, &phase.generate(), r.time());
+        let mut next_aov = SampleAovs::default();
+        let incoming = ray_color(
+            &scattered,
+            background,
+            world,
+            lights.clone(),
+            depth - 1,
+            Some(&mut next_aov),
+        );
+        // The phase pdf equals the phase function, so only the albedo remains.
+        let hit_emission = srec.attenuation
+            * next_aov.emission
+            * light_mis_weight(
+                &rec,
+                &scattered,
+                next_aov.depth,
+                &lights,
+                phase.value(&scattered.direction()),
+            );
+        let indirect = srec.attenuation * (incoming - next_aov.emission);
+        if let Some(aov) = aov {
+            aov.direct = direct + hit_emission;
+            aov.indirect = indirect;
+        }
+        return emitted + direct + hit_emission + indirect;
+    }
+
+    // With AOVs requested, the next vertex reports its own emission so the
+    // light arriving here can be split into direct and indirect.
+    let mut next_aov = SampleAovs::default();
+    let next_aov_ref = if aov.is_some() {
+        Some(&mut next_aov)
+    } else {
+        None
+    };
+
     if srec.is_specular {
-        return srec.attenuation
-            * ray_color(&srec.specular_ray, background, world, lights, depth - 1);
+        let incoming = ray_color(
+            &srec.specular_ray,
+            background,
+            world,
+            lights,
+            depth - 1,
+            next_aov_ref,
+        );
+        if let Some(aov) = aov {
+            aov.direct = srec.attenuation * next_aov.emission;
+            aov.indirect = srec.attenuation * (incoming - next_aov.emission);
+        }
+        return srec.attenuation * incoming;
     }
 
-    let light_ptr = Arc::new(HittablePdf::construct(lights.clone(), &rec.p));
+    let light_ptr = Arc::new(HittablePdf::construct(lights.clone(), &rec.p, r.time()));
     let p = MixturePdf::construct(light_ptr, (*srec.pdf_ptr.as_ref().unwrap()).clone());
     let scattered = Ray::construct(&rec.p, &p.generate(), r.time());
     let pdf_val = p.value(&scattered.direction());
@@ -127,15 +217,160 @@ pub fn ray_color(
     // scattered = Ray::construct(&rec.p, &mixture_pdf.generate(), r.time());
     // pdf = mixture_pdf.value(&scattered.direction());
 
-    emitted
-        + srec.attenuation
-            * rec
-                .mat_ptr
-                .as_ref()
-                .unwrap()
-                .scattering_pdf(r, &rec, &scattered)
-            * ray_color(&scattered, background, world, lights, depth - 1)
-            / pdf_val
+    let weight = srec.attenuation
+        * rec
+            .mat_ptr
+            .as_ref()
+            .unwrap()
+            .scattering_pdf(r, &rec, &scattered)
+        / pdf_val;
+    let incoming = ray_color(
+        &scattered,
+        background,
+        world,
+        lights,
+        depth - 1,
+        next_aov_ref,
+    );
+    if let Some(aov) = aov {
+        aov.direct = weight * next_aov.emission;
+        aov.indirect = weight * (incoming - next_aov.emission);
+    }
+    emitted + weight * incoming
+}
+
+// Light reaching a scattering event in a medium straight from `lights`. The
+// shadow ray is attenuated by the transmittance of everything in between,
+// which media estimate by ratio tracking. Balance-heuristic weighted against
+// phase sampling, see light_mis_weight().
+fn sample_light_through_media(
+    r: &Ray,
+    rec: &HitRecord,
+    srec: &ScatterRecord,
+    world: &dyn Hittable,
+    lights: &Arc<dyn Hittable>,
+) -> Color3 {
+    let light_pdf = HittablePdf::construct(lights.clone(), &rec.p, r.time());
+    let shadow = Ray::construct(&rec.p, &light_pdf.generate(), r.time());
+    let mut light_rec = HitRecord::new();
+    if !lights.hit(&shadow, 0.001, f64::INFINITY, &mut light_rec) {
+        return Color3::new();
+    }
+    // Emission is taken from the world's copy of the light, which knows
+    // which of its sides faces out.
+    let t_light = light_rec.t;
+    let mut surface = HitRecord::new();
+    if !world.hit(
+        &shadow,
+        t_light * (1.0 - 1e-6),
+        t_light * (1.0 + 1e-6),
+        &mut surface,
+    ) {
+        return Color3::new();
+    }
+    let emitted = surface
+        .mat_ptr
+        .as_ref()
+        .unwrap()
+        .emitted(&shadow, &surface, surface.u, surface.v, &surface.p);
+    let light_value = light_pdf.value(&shadow.direction());
+    if light_value <= 0.0 || emitted.near_zero() {
+        return Color3::new();
+    }
+    let phase_value = rec
+        .mat_ptr
+        .as_ref()
+        .unwrap()
+        .scattering_pdf(r, rec, &shadow);
+    let tr = world.transmittance(&shadow, 0.001, surface.t * (1.0 - 1e-6));
+    srec.attenuation * emitted * (tr * phase_value / (light_value + phase_value))
+}
+
+// Balance-heuristic weight for emission found by a phase-sampled ray whose
+// next vertex is `hit_depth` away. Emission that sample_light_through_media
+// could not have sampled keeps its full weight.
+fn light_mis_weight(
+    rec: &HitRecord,
+    scattered: &Ray,
+    hit_depth: f64,
+    lights: &Arc<dyn Hittable>,
+    phase_value: f64,
+) -> f64 {
+    let mut light_rec = HitRecord::new();
+    if !lights.hit(scattered, 0.001, f64::INFINITY, &mut light_rec) {
+        return 1.0;
+    }
+    let light_depth = light_rec.t * scattered.direction().length();
+    if (light_depth - hit_depth).abs() > 1e-6 * hit_depth {
+        return 1.0;
+    }
+    let light_value = lights.pdf_value(&rec.p, &scattered.direction(), scattered.time());
+    phase_value / (light_value + phase_value)
+}
+
+pub fn ray_color_spectral(
+    r: &Ray,
+    lambdas: &Wavelengths,
+    background: &Color3,
+    world: &dyn Hittable,
+    lights: Arc<dyn Hittable>,
+    depth: i32,
+) -> SpectralSample {
+    // Same estimator as ray_color, carried at HERO_COUNT wavelengths at once.
+    // `r` travels at the hero wavelength, lambdas[0].
+    let mut rec: HitRecord = HitRecord::new();
+    if depth <= 0 {
+        return [0.0; HERO_COUNT];
+    }
+    if !world.hit(r, 0.001, f64::INFINITY, &mut rec) {
+        return spectrum::rgb_to_spectral_sample(background, lambdas);
+    }
+    rec.compute_differentials(r);
+
+    let mut srec = ScatterRecord::new();
+    let emitted = spectrum::rgb_to_spectral_sample(
+        &rec.mat_ptr
+            .as_ref()
+            .unwrap()
+            .emitted(r, &rec, rec.u, rec.v, &rec.p),
+        lambdas,
+    );
+    if !rec.mat_ptr.as_ref().unwrap().scatter(r, &rec, &mut srec) {
+        return emitted;
+    }
+    let attenuation = spectrum::rgb_to_spectral_sample(&srec.attenuation, lambdas);
+
+    let mut result = emitted;
+    if srec.is_specular {
+        let specular_ray = srec.specular_ray.with_wavelength(r.wavelength());
+        let incoming =
+            ray_color_spectral(&specular_ray, lambdas, background, world, lights, depth - 1);
+        if srec.is_dispersive {
+            // The companion wavelengths would have taken other directions;
+            // only the hero survives, carrying their share of the estimate.
+            result[0] += HERO_COUNT as f64 * attenuation[0] * incoming[0];
+            return result;
+        }
+        for i in 0..HERO_COUNT {
+            result[i] += attenuation[i] * incoming[i];
+        }
+        return result;
+    }
+
+    let light_ptr = Arc::new(HittablePdf::construct(lights.clone(), &rec.p, r.time()));
+    let p = MixturePdf::construct(light_ptr, (*srec.pdf_ptr.as_ref().unwrap()).clone());
+    let scattered = Ray::construct(&rec.p, &p.generate(), r.time()).with_wavelength(r.wavelength());
+    let pdf_val = p.value(&scattered.direction());
+    let scattering_pdf = rec
+        .mat_ptr
+        .as_ref()
+        .unwrap()
+        .scattering_pdf(r, &rec, &scattered);
+    let incoming = ray_color_spectral(&scattered, lambdas, background, world, lights, depth - 1);
+    for i in 0..HERO_COUNT {
+        result[i] += attenuation[i] * scattering_pdf * incoming[i] / pdf_val;
+    }
+    result
 }
 
 pub fn write_color(pixel_color: &Color3, samples_per_pixel: u32) -> [u8; 3] {
@@ -278,18 +513,9 @@ pub fn write_color(pixel_color: &Color3, samples_per_pixel: u32) -> [u8; 3] {
 //     objects
 // }
 // pub fn earth() -> HittableList {
-//     let img = image::open("earthmap.jpg").expect("Failed to open image");
-//     let width: u32 = img.width();
-//     let height: u32 = img.height();
-//     let mut data: Vec<u8> = Vec::new();
-//     for (_x, _y, pixel) in img.pixels() {
-//         let rgba = pixel.0;
-//         let (r, g, b) = (rgba[0], rgba[1], rgba[2]);
-//         data.push(r);
-//         data.push(g);
-//         data.push(b);
-//     }
-//     let earth_texture: Arc<dyn Texture> = Arc::new(ImageTexture::construct(&data, width, height));
+//     let earth_texture: Arc<dyn Texture> = Arc::new(
+//         ImageTexture::from_path("earthmap.jpg", ColorSpace::Srgb).expect("Failed to open image"),
+//     );
 //     let earth_surface = Arc::new(Lambertian::construct_texture(earth_texture));
 //     let globe = Arc::new(Sphere::construct(
 //         &Point3::construct(&[0.0, 0.0, 0.0]),
@@ -432,6 +658,176 @@ pub fn cornell_box() -> HittableList {
     objects
 }
 
+// Light sampling list for the Cornell box scenes.
+pub fn cornell_lights(glass: bool) -> HittableList {
+    let mut lights = HittableList::new();
+    lights.add(Arc::new(XzRect::construct(
+        213.0,
+        343.0,
+        227.0,
+        332.0,
+        554.0,
+        Arc::new(DiffuseLight::construct_color(&Color3::construct(&[
+            15.0, 15.0, 15.0,
+        ]))),
+    )));
+    if glass {
+        lights.add(Arc::new(Sphere::construct(
+            &Point3::construct(&[190.0, 90.0, 190.0]),
+            90.0,
+            Arc::new(Dielectric::construct(1.5)),
+        )));
+    }
+    lights
+}
+
+// Empty Cornell box holding a cloud of Perlin turbulence.
+pub fn cornell_grid_smoke() -> HittableList {
+    let mut objects = HittableList::new();
+
+    let red = Arc::new(Lambertian::construct(&Color3::construct(&[
+        0.65, 0.05, 0.05,
+    ])));
+    let white = Arc::new(Lambertian::construct(&Color3::construct(&[
+        0.73, 0.73, 0.73,
+    ])));
+    let green = Arc::new(Lambertian::construct(&Color3::construct(&[
+        0.12, 0.45, 0.15,
+    ])));
+    let light = Arc::new(DiffuseLight::construct_color(&Color3::construct(&[
+        15.0, 15.0, 15.0,
+    ])));
+
+    objects.add(Arc::new(YzRect::construct(
+        0.0, 555.0, 0.0, 555.0, 555.0, green,
+    )));
+    objects.add(Arc::new(YzRect::construct(
+        0.0, 555.0, 0.0, 555.0, 0.0, red,
+    )));
+    objects.add(Arc::new(FlipFace::construct(Arc::new(XzRect::construct(
+        213.0, 343.0, 227.0, 332.0, 554.0, light,
+    )))));
+    objects.add(Arc::new(XzRect::construct(
+        0.0,
+        555.0,
+        0.0,
+        555.0,
+        0.0,
+        white.clone(),
+    )));
+    objects.add(Arc::new(XzRect::construct(
+        0.0,
+        555.0,
+        0.0,
+        555.0,
+        555.0,
+        white.clone(),
+    )));
+    objects.add(Arc::new(XyRect::construct(
+        0.0, 555.0, 0.0, 555.0, 555.0, white,
+    )));
+
+    let grid = Arc::new(DensityGrid::from_turbulence(&Perlin::new(), 64, 4.0));
+    objects.add(Arc::new(GridVolume::construct(
+        grid,
+        &Point3::construct(&[100.0, 0.0, 100.0]),
+        &Point3::construct(&[455.0, 420.0, 455.0]),
+        0.02,
+        &Color3::construct(&[0.8, 0.8, 0.8]),
+        0.3,
+    )));
+
+    objects
+}
+
+// Cornell box whose tall block follows a keyframed path, for motion blur on
+// transformed objects. Times are in seconds, as for the camera track.
+pub fn cornell_motion() -> HittableList {
+    let mut objects = HittableList::new();
+
+    let red = Arc::new(Lambertian::construct(&Color3::construct(&[
+        0.65, 0.05, 0.05,
+    ])));
+    let white = Arc::new(Lambertian::construct(&Color3::construct(&[
+        0.73, 0.73, 0.73,
+    ])));
+    let green = Arc::new(Lambertian::construct(&Color3::construct(&[
+        0.12, 0.45, 0.15,
+    ])));
+    let light = Arc::new(DiffuseLight::construct_color(&Color3::construct(&[
+        15.0, 15.0, 15.0,
+    ])));
+
+    objects.add(Arc::new(YzRect::construct(
+        0.0, 555.0, 0.0, 555.0, 555.0, green,
+    )));
+    objects.add(Arc::new(YzRect::construct(
+        0.0, 555.0, 0.0, 555.0, 0.0, red,
+    )));
+    objects.add(Arc::new(FlipFace::construct(Arc::new(XzRect::construct(
+        213.0, 343.0, 227.0, 332.0, 554.0, light,
+    )))));
+    objects.add(Arc::new(XzRect::construct(
+        0.0,
+        555.0,
+        0.0,
+        555.0,
+        0.0,
+        white.clone(),
+    )));
+    objects.add(Arc::new(XzRect::construct(
+        0.0,
+        555.0,
+        0.0,
+        555.0,
+        555.0,
+        white.clone(),
+    )));
+    objects.add(Arc::new(XyRect::construct(
+        0.0,
+        555.0,
+        0.0,
+        555.0,
+        555.0,
+        white.clone(),
+    )));
+
+    let block: Arc<dyn Hittable> = Arc::new(Box::construct(
+        &Point3::construct(&[-82.5, 0.0, -82.5]),
+        &Point3::construct(&[82.5, 330.0, 82.5]),
+        white,
+    ));
+    let translation = Track::construct(
+        &[
+            Keyframe::construct(0.0, Vec3::construct(&[347.5, 0.0, 377.5])),
+            Keyframe::construct(1.0, Vec3::construct(&[300.0, 0.0, 330.0])).with_handles(
+                Vec3::construct(&[330.0, 0.0, 370.0]),
+                Vec3::construct(&[270.0, 0.0, 290.0]),
+            ),
+            Keyframe::construct(2.0, Vec3::construct(&[250.0, 0.0, 300.0])),
+        ],
+        Interpolation::Bezier,
+    );
+    let rotation = Track::construct(
+        &[
+            Keyframe::construct(0.0, Vec3::construct(&[0.0, 15.0, 0.0])),
+            Keyframe::construct(1.0, Vec3::construct(&[0.0, 45.0, 0.0])),
+            Keyframe::construct(2.0, Vec3::construct(&[0.0, 30.0, 0.0])),
+        ],
+        Interpolation::CatmullRom,
+    );
+    objects.add(Arc::new(MotionTransform::construct(
+        block,
+        TransformTrack::construct(
+            translation,
+            rotation,
+            Track::constant(Vec3::construct(&[1.0, 1.0, 1.0])),
+        ),
+    )));
+
+    objects
+}
+
 // pub fn cornell_smoke() -> HittableList {
 //     let mut objects = HittableList::new();
 
@@ -598,18 +994,9 @@ pub fn cornell_box() -> HittableList {
 //         &Color3::construct(&[1.0, 1.0, 1.0]),
 //     )));
 
-//     let img = image::open("earthmap.jpg").expect("Failed to open image");
-//     let width: u32 = img.width();
-//     let height: u32 = img.height();
-//     let mut data: Vec<u8> = Vec::new();
-//     for (_x, _y, pixel) in img.pixels() {
-//         let rgba = pixel.0;
-//         let (r, g, b) = (rgba[0], rgba[1], rgba[2]);
-//         data.push(r);
-//         data.push(g);
-//         data.push(b);
-//     }
-//     let earth_texture: Arc<dyn Texture> = Arc::new(ImageTexture::construct(&data, width, height));
+//     let earth_texture: Arc<dyn Texture> = Arc::new(
+//         ImageTexture::from_path("earthmap.jpg", ColorSpace::Srgb).expect("Failed to open image"),
+//     );
 //     let earth_surface = Arc::new(Lambertian::construct_texture(earth_texture));
 //     let globe = Arc::new(Sphere::construct(
 //         &Point3::construct(&[400.0, 200.0, 400.0]),
@@ -648,41 +1035,478 @@ pub fn cornell_box() -> HittableList {
 //     objects
 // }
 
+// Image
+const ASPECT_RATIO: f64 = 1.0;
+const IMAGE_WIDTH: u32 = 600;
+const IMAGE_HEIGHT: u32 = (IMAGE_WIDTH as f64 / ASPECT_RATIO) as u32;
+const SAMPLES_PER_PIXEL: u32 = 1080;
+const MAX_DEPTH: i32 = 50;
+const TILE_SIZE: u32 = 16;
+const MAX_PASS_SAMPLES: u32 = 64;
+// Trace hero wavelengths instead of RGB, e.g. to show glass dispersion.
+const SPECTRAL: bool = false;
+
+// Command line: [--frames FIRST LAST] [--fps N] [--shutter FRACTION].
+// Without --frames a single still is rendered.
+struct RenderArgs {
+    frames: Option<(u32, u32)>,
+    fps: f64,
+    // Fraction of the frame interval the shutter stays open.
+    shutter: f64,
+    filter: Arc<dyn Filter>,
+    aovs: Vec<Aov>,
+    // Also write a denoised copy of the image.
+    denoise: bool,
+    // Error threshold for adaptive sampling; None samples every pixel fully.
+    adaptive: Option<f64>,
+    min_samples: u32,
+    // Render progressively, writing a preview every so many seconds or passes.
+    preview_seconds: Option<f64>,
+    preview_passes: Option<u32>,
+    scene: String,
+}
+impl RenderArgs {
+    fn parse() -> Self {
+        let mut parsed = Self {
+            frames: None,
+            fps: 24.0,
+            shutter: 0.5,
+            filter: Arc::new(filter::BoxFilter::construct(0.5)),
+            aovs: Vec::new(),
+            denoise: false,
+            adaptive: None,
+            min_samples: 16,
+            preview_seconds: None,
+            preview_passes: None,
+            scene: String::from("cornell"),
+        };
+        let mut filter_name = String::from("box");
+        let mut filter_radius = None;
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        let value = |i: usize| -> f64 {
+            args.get(i)
+                .and_then(|a| a.parse().ok())
+                .unwrap_or_else(|| Self::usage())
+        };
+        let mut i = 0;
+        while i < args.len() {
+            match args[i].as_str() {
+                "--frames" => {
+                    parsed.frames = Some((value(i + 1) as u32, value(i + 2) as u32));
+                    i += 3;
+                }
+                "--fps" => {
+                    parsed.fps = value(i + 1);
+                    i += 2;
+                }
+                "--shutter" => {
+                    parsed.shutter = value(i + 1);
+                    i += 2;
+                }
+                "--filter" => {
+                    filter_name = args.get(i + 1).cloned().unwrap_or_else(|| Self::usage());
+                    i += 2;
+                }
+                "--adaptive" => {
+                    parsed.adaptive = Some(value(i + 1));
+                    i += 2;
+                }
+                "--min-spp" => {
+                    parsed.min_samples = value(i + 1) as u32;
+                    i += 2;
+                }
+                "--preview-seconds" => {
+                    parsed.preview_seconds = Some(value(i + 1));
+                    i += 2;
+                }
+                "--preview-passes" => {
+                    parsed.preview_passes = Some(value(i + 1) as u32);
+                    i += 2;
+                }
+                "--scene" => {
+                    parsed.scene = args.get(i + 1).cloned().unwrap_or_else(|| Self::usage());
+                    i += 2;
+                }
+                "--denoise" => {
+                    parsed.denoise = true;
+                    i += 1;
+                }
+                "--aov" => {
+                    // Comma-separated AOV names, or "all".
+                    let names = args.get(i + 1).unwrap_or_else(|| Self::usage());
+                    parsed.aovs = if names == "all" {
+                        Aov::ALL.to_vec()
+                    } else {
+                        names
+                            .split(',')
+                            .map(|name| Aov::from_name(name).unwrap_or_else(|| Self::usage()))
+                            .collect()
+                    };
+                    i += 2;
+                }
+                "--filter-radius" => {
+                    filter_radius = Some(value(i + 1));
+                    i += 2;
+                }
+                _ => Self::usage(),
+            }
+        }
+        parsed.filter =
+            filter::filter_by_name(&filter_name, filter_radius).unwrap_or_else(|| Self::usage());
+        if parsed.adaptive.is_some() && filter_name != "box" {
+            eprintln!(
+                "warning: --adaptive with a {} filter biases the image where sample density changes",
+                filter_name
+            );
+        }
+        parsed
+    }
+    fn usage() -> ! {
+        eprintln!(
+            "usage: raytracer [--frames FIRST LAST] [--fps N] [--shutter FRACTION] \
+             [--filter box|gaussian|mitchell|lanczos|blackman-harris] [--filter-radius PIXELS] \
+             [--aov all|NAME,...] [--denoise] [--adaptive THRESHOLD] [--min-spp N] \
+             [--preview-seconds S] [--preview-passes N] [--scene cornell|smoke|motion]\n\
+             --adaptive expects the box filter: wider filters blend neighbours that took \
+             different numbers of samples, which biases the result.\n\
+             AOV names: albedo normal depth position object_id material_id direct indirect \
+             emission sample_count variance"
+        );
+        exit(1);
+    }
+}
+
+fn numbered_path(path: &Path, frame: u32) -> PathBuf {
+    // image.jpg -> image_0001.jpg
+    let stem = path.file_stem().unwrap().to_str().unwrap();
+    let extension = path.extension().unwrap().to_str().unwrap();
+    path.with_file_name(format!("{}_{:04}.{}", stem, frame, extension))
+}
+
+fn sample_color(
+    cam: &dyn Camera,
+    x: f64,
+    y: f64,
+    background: &Color3,
+    world: &HittableList,
+    lights_ptr: Arc<HittableList>,
+    aov: Option<&mut SampleAovs>,
+) -> Color3 {
+    // Radiance arriving at film position (x, y), in pixels. AOVs are only
+    // recorded by the RGB integrator.
+    let u: f64 = x / (IMAGE_WIDTH - 1) as f64;
+    let v: f64 = y / (IMAGE_HEIGHT - 1) as f64;
+    let r: Ray = match cam.get_ray_differential(
+        u,
+        v,
+        1.0 / (IMAGE_WIDTH - 1) as f64,
+        1.0 / (IMAGE_HEIGHT - 1) as f64,
+    ) {
+        Some(r) => r,
+        None => return Color3::new(),
+    };
+    if SPECTRAL {
+        let lambdas = spectrum::sample_wavelengths();
+        let radiance = ray_color_spectral(
+            &r.with_wavelength(lambdas[0]),
+            &lambdas,
+            background,
+            world,
+            lights_ptr,
+            MAX_DEPTH,
+        );
+        return spectrum::spectral_sample_to_rgb(&radiance, &lambdas);
+    }
+    ray_color(&r, background, world, lights_ptr, MAX_DEPTH, aov)
+}
+
+fn encode_image<F: Fn(u32, u32) -> Color3>(path: &Path, pixel: F) -> image::ImageResult<()> {
+    // `pixel` is indexed in film order, with j = 0 at the bottom.
+    let quality = 100;
+    let mut img: RgbImage = ImageBuffer::new(IMAGE_WIDTH, IMAGE_HEIGHT);
+    for j in 0..IMAGE_HEIGHT {
+        for i in 0..IMAGE_WIDTH {
+            let rgb: [u8; 3] = write_color(&pixel(i, j), 1);
+            img.put_pixel(i, IMAGE_HEIGHT - j - 1, image::Rgb(rgb));
+        }
+    }
+    let output_image = image::DynamicImage::ImageRgb8(img);
+    let mut output_file = File::create(path)?;
+    output_image.write_to(&mut output_file, image::ImageOutputFormat::Jpeg(quality))
+}
+
+fn write_image<F: Fn(u32, u32) -> Color3>(path: &Path, pixel: F) {
+    println!(
+        "Output image as \"{}\"",
+        style(path.to_str().unwrap()).yellow()
+    );
+    match encode_image(path, pixel) {
+        Ok(_) => {}
+        Err(_) => println!("{}", style("Outputing image fails.").red()),
+    }
+}
+
+fn write_preview(film: &Film, path: &Path) {
+    // Written beside the final image and renamed into place, so a viewer
+    // never picks up a half-written file.
+    let partial = path.with_extension("part");
+    let result = encode_image(&partial, |i, j| film.color(i, j))
+        .map_err(|e| e.to_string())
+        .and_then(|_| std::fs::rename(&partial, path).map_err(|e| e.to_string()));
+    if let Err(e) = result {
+        println!("{}", style(format!("Outputing preview fails: {}", e)).red());
+    }
+}
+
+// Everything a render thread needs to trace samples.
+#[derive(Clone)]
+struct SceneRef {
+    cam: Arc<dyn Camera>,
+    world: Arc<HittableList>,
+    lights_ptr: Arc<HittableList>,
+    background: Color3,
+}
+
+// Number of tiles across and down the image.
+fn tile_counts() -> (u32, u32) {
+    (
+        (IMAGE_WIDTH - 1) / TILE_SIZE + 1,
+        (IMAGE_HEIGHT - 1) / TILE_SIZE + 1,
+    )
+}
+
+// Samples each pixel gets in the next pass, and the tiles holding any of
+// them, noisiest first. Without a threshold every pixel simply gets `batch`
+// more samples until it reaches SAMPLES_PER_PIXEL.
+fn plan_pass(film: &Film, threshold: Option<f64>, batch: u32) -> (Vec<u32>, Vec<u32>) {
+    let (tiles_x, tiles_y) = tile_counts();
+    let mut samples = vec![0; (IMAGE_WIDTH * IMAGE_HEIGHT) as usize];
+    let mut tile_errors: Vec<(u32, f64)> = Vec::new();
+    for tile_index in 0..tiles_x * tiles_y {
+        let x0 = (tile_index % tiles_x) * TILE_SIZE;
+        let y0 = (tile_index / tiles_x) * TILE_SIZE;
+        let mut tile_error: Option<f64> = None;
+        for j in y0..(y0 + TILE_SIZE).min(IMAGE_HEIGHT) {
+            for i in x0..(x0 + TILE_SIZE).min(IMAGE_WIDTH) {
+                let index = (j * IMAGE_WIDTH + i) as usize;
+                let taken = film.pixels[index].sample_count;
+                if taken >= SAMPLES_PER_PIXEL {
+                    continue;
+                }
+                let error = film.neighbourhood_error(i, j);
+                if let Some(threshold) = threshold {
+                    if taken >= batch && error <= threshold {
+                        continue;
+                    }
+                }
+                samples[index] = batch.min(SAMPLES_PER_PIXEL - taken);
+                tile_error = Some(tile_error.map_or(error, |e: f64| e.max(error)));
+            }
+        }
+        if let Some(error) = tile_error {
+            tile_errors.push((tile_index, error));
+        }
+    }
+    tile_errors.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+    (samples, tile_errors.iter().map(|t| t.0).collect())
+}
+
+fn render_pass(
+    scene: &SceneRef,
+    film: &Arc<Mutex<Film>>,
+    samples: Arc<Vec<u32>>,
+    tiles: Arc<Vec<u32>>,
+    record_aovs: bool,
+    progress: &ProgressBar,
+) {
+    // Threads take tiles in turn, render them into private film tiles and
+    // merge those back, so samples can splat across tile borders.
+    let (tiles_x, _) = tile_counts();
+    let next_tile = Arc::new(AtomicU32::new(0));
+
+    let thread_num: u32 = 18;
+    let mut handles: Vec<thread::JoinHandle<()>> = Vec::new();
+    for _s in 0..thread_num {
+        let scene = scene.clone();
+        let film = film.clone();
+        let samples = samples.clone();
+        let tiles = tiles.clone();
+        let next_tile = next_tile.clone();
+        let progress = progress.clone();
+
+        let handle = thread::spawn(move || loop {
+            let next = next_tile.fetch_add(1, Ordering::Relaxed) as usize;
+            if next >= tiles.len() {
+                break;
+            }
+            let tile_index = tiles[next];
+            let x0 = (tile_index % tiles_x) * TILE_SIZE;
+            let y0 = (tile_index / tiles_x) * TILE_SIZE;
+            let x1 = (x0 + TILE_SIZE).min(IMAGE_WIDTH);
+            let y1 = (y0 + TILE_SIZE).min(IMAGE_HEIGHT);
+            let mut tile = film.lock().unwrap().tile(x0, y0, x1, y1);
+            for j in y0..y1 {
+                let mut taken = 0;
+                for i in x0..x1 {
+                    let count = samples[(j * IMAGE_WIDTH + i) as usize];
+                    for _t in 0..count {
+                        let x = i as f64 + random_double();
+                        let y = j as f64 + random_double();
+                        let mut aov = SampleAovs::default();
+                        let color = sample_color(
+                            &*scene.cam,
+                            x,
+                            y,
+                            &scene.background,
+                            &scene.world,
+                            scene.lights_ptr.clone(),
+                            if record_aovs { Some(&mut aov) } else { None },
+                        );
+                        tile.add_sample(x, y, &color, if record_aovs { Some(&aov) } else { None });
+                    }
+                    taken += count;
+                }
+                progress.inc(taken as u64);
+            }
+            film.lock().unwrap().merge_tile(&tile);
+        });
+        handles.push(handle);
+    }
+    for thread in handles {
+        thread.join().unwrap();
+    }
+}
+
+fn render(
+    cam: Arc<dyn Camera>,
+    world: &HittableList,
+    lights_ptr: Arc<HittableList>,
+    background: &Color3,
+    args: &RenderArgs,
+    path: &Path,
+) {
+    // Render
+    let scene = SceneRef {
+        cam,
+        world: Arc::new(world.clone()),
+        lights_ptr,
+        background: *background,
+    };
+
+    let progress = if option_env!("CI").unwrap_or_default() == "true" {
+        ProgressBar::hidden()
+    } else {
+        ProgressBar::new((IMAGE_HEIGHT * IMAGE_WIDTH * SAMPLES_PER_PIXEL) as u64)
+    };
+
+    let mut recorded_aovs = args.aovs.clone();
+    if args.denoise {
+        recorded_aovs.extend_from_slice(&Denoiser::AOVS);
+    }
+    let film = Arc::new(Mutex::new(
+        Film::construct(IMAGE_WIDTH, IMAGE_HEIGHT, args.filter.clone()).with_aovs(&recorded_aovs),
+    ));
+    let record_aovs = !recorded_aovs.is_empty();
+
+    // With --adaptive every pixel first gets min_samples, then further
+    // passes of the same size go only where the error is above threshold.
+    // Progressive renders double the sample count each pass, up to
+    // MAX_PASS_SAMPLES at a time, so previews keep coming.
+    let progressive = args.preview_seconds.is_some() || args.preview_passes.is_some();
+    let stem = path.file_stem().unwrap().to_str().unwrap();
+    let preview_path = path.with_file_name(format!("{}_preview.jpg", stem));
+    if progressive {
+        println!(
+            "Writing previews to \"{}\"",
+            style(preview_path.to_str().unwrap()).yellow()
+        );
+    }
+    let mut last_preview = Instant::now();
+    let mut samples_taken = 0;
+    let mut pass = 0;
+    loop {
+        let batch = match args.adaptive {
+            Some(_) => args.min_samples.clamp(2, SAMPLES_PER_PIXEL),
+            None if progressive => samples_taken.clamp(1, MAX_PASS_SAMPLES),
+            None => SAMPLES_PER_PIXEL,
+        };
+        let (samples, tiles) = plan_pass(&film.lock().unwrap(), args.adaptive, batch);
+        if tiles.is_empty() {
+            break;
+        }
+        render_pass(
+            &scene,
+            &film,
+            Arc::new(samples),
+            Arc::new(tiles),
+            record_aovs,
+            &progress,
+        );
+        samples_taken += batch;
+        pass += 1;
+
+        let passes_due = matches!(args.preview_passes, Some(n) if pass % n.max(1) == 0);
+        let time_due = matches!(
+            args.preview_seconds,
+            Some(t) if last_preview.elapsed().as_secs_f64() >= t
+        );
+        if passes_due || time_due {
+            write_preview(&film.lock().unwrap(), &preview_path);
+            last_preview = Instant::now();
+        }
+    }
+    progress.finish();
+
+    let film = film.lock().unwrap();
+    if args.adaptive.is_some() {
+        let total: u64 = film.pixels.iter().map(|p| p.sample_count as u64).sum();
+        println!(
+            "Average {:.1} samples per pixel",
+            total as f64 / (IMAGE_WIDTH * IMAGE_HEIGHT) as f64
+        );
+    }
+    write_image(path, |i, j| film.color(i, j));
+
+    if args.denoise {
+        let denoised = Denoiser::new().denoise(&film);
+        let denoised_path = path.with_file_name(format!("{}_denoised.jpg", stem));
+        write_image(&denoised_path, |i, j| {
+            denoised[(j * IMAGE_WIDTH + i) as usize]
+        });
+    }
+    for aov in args.aovs.iter() {
+        let aov_path = path.with_file_name(format!("{}_{}.pfm", stem, aov.name()));
+        match film.write_aov(*aov, &aov_path) {
+            Ok(_) => println!(
+                "Output {} as \"{}\"",
+                aov.name(),
+                style(aov_path.to_str().unwrap()).yellow()
+            ),
+            Err(e) => println!(
+                "{}",
+                style(format!("Outputing {} fails: {}", aov.name(), e)).red()
+            ),
+        }
+    }
+}
+
 fn main() {
     // let img =
+    let args = RenderArgs::parse();
 
     let path = std::path::Path::new("output/book3/image12.jpg");
     let prefix = path.parent().unwrap();
     std::fs::create_dir_all(prefix).expect("Cannot create all the parents");
 
-    // Image
-    const ASPECT_RATIO: f64 = 1.0;
-    const IMAGE_WIDTH: u32 = 600;
-    const IMAGE_HEIGHT: u32 = (IMAGE_WIDTH as f64 / ASPECT_RATIO) as u32;
-    const SAMPLES_PER_PIXEL: u32 = 1080;
-    const MAX_DEPTH: i32 = 50;
-
     // World
     // let mut world = random_scene();
 
-    let world: HittableList = cornell_box();
-    let mut lights = HittableList::new();
-    lights.add(Arc::new(XzRect::construct(
-        213.0,
-        343.0,
-        227.0,
-        332.0,
-        554.0,
-        Arc::new(DiffuseLight::construct_color(&Color3::construct(&[
-            15.0, 15.0, 15.0,
-        ]))),
-    )));
-    lights.add(Arc::new(Sphere::construct(
-        &Point3::construct(&[190.0, 90.0, 190.0]),
-        90.0,
-        Arc::new(Dielectric::construct(1.5)),
-    )));
-
+    let (world, lights) = match args.scene.as_str() {
+        "cornell" => (cornell_box(), cornell_lights(true)),
+        "smoke" => (cornell_grid_smoke(), cornell_lights(false)),
+        "motion" => (cornell_motion(), cornell_lights(false)),
+        _ => RenderArgs::usage(),
+    };
     let lights_ptr = Arc::new(lights);
 
     let lookfrom = Point3::construct(&[278.0, 278.0, -800.0]);
@@ -708,92 +1532,52 @@ fn main() {
     let vup: Vec3 = Vec3::construct(&[0.0, 1.0, 0.0]);
     let dist_to_focus: f64 = 10.0;
 
-    let cam: Camera = Camera::new(
-        &lookfrom,
-        &lookat,
-        &vup,
-        &[vfov, ASPECT_RATIO, aperture, dist_to_focus],
-        0.0,
-        1.0,
+    let camera_builder = CameraBuilder::new()
+        .vup(&vup)
+        .vfov(vfov)
+        .aspect_ratio(ASPECT_RATIO)
+        .aperture_diameter(aperture)
+        .focus_distance(dist_to_focus);
+    // Times are in seconds; a still image is taken at time 0.
+    let camera_track = CameraTrack::construct(
+        Track::construct(
+            &[
+                Keyframe::construct(0.0, lookfrom),
+                Keyframe::construct(2.0, Point3::construct(&[278.0, 278.0, -700.0])),
+            ],
+            Interpolation::CatmullRom,
+        ),
+        Track::constant(lookat),
     );
 
-    // Render
-    let quality = 100;
-    let mut img: RgbImage = ImageBuffer::new(IMAGE_WIDTH, IMAGE_HEIGHT);
-
-    let progress = if option_env!("CI").unwrap_or_default() == "true" {
-        ProgressBar::hidden()
-    } else {
-        ProgressBar::new((IMAGE_HEIGHT * IMAGE_WIDTH) as u64)
-    };
-
-    let thread_num: u32 = 18;
-    for j in (0..IMAGE_HEIGHT).rev() {
-        for i in 0..IMAGE_WIDTH {
-            let pixel = img.get_pixel_mut(i, IMAGE_HEIGHT - j - 1);
-            let mut pixel_color: Color3 = Color3::construct(&[0.0, 0.0, 0.0]);
-
-            let mut handles: Vec<thread::JoinHandle<()>> = Vec::new();
-            let mut recv: Vec<mpsc::Receiver<Color3>> = Vec::new();
-            for _s in 0..thread_num {
-                let (tx, rx) = mpsc::channel();
-                recv.push(rx);
-                let cam = cam.clone();
-                let world = world.clone();
-                let max_depth = MAX_DEPTH;
-                let image_width = IMAGE_WIDTH;
-                let image_height = IMAGE_HEIGHT;
-                let i_f64 = i as f64;
-                let j_f64 = j as f64;
-                let lights_ptr = lights_ptr.clone();
-
-                let handle = thread::spawn(move || {
-                    for _t in 0..(SAMPLES_PER_PIXEL / thread_num) {
-                        let u: f64 = (i_f64 + random_double()) / (image_width - 1) as f64;
-                        let v: f64 = (j_f64 + random_double()) / (image_height - 1) as f64;
-                        let r: Ray = cam.get_ray(u, v);
-                        tx.send(ray_color(
-                            &r,
-                            &background,
-                            &world,
-                            lights_ptr.clone(),
-                            max_depth,
-                        ))
-                        .unwrap();
-                    }
-                });
-                handles.push(handle);
-            }
-            let mut cnt = 0u32;
-            // 第一层遍历：遍历接收者
-            for rec in recv {
-                // 第二层遍历：遍历接收者收到的数据
-                for received in rec {
-                    pixel_color += received;
-                    cnt += 1;
-                }
-            }
-            for thread in handles {
-                thread.join().unwrap();
+    match args.frames {
+        None => {
+            let cam: Arc<dyn Camera> =
+                Arc::new(camera_track.camera_at(&camera_builder, 0.0, 0.0, 1.0));
+            render(cam, &world, lights_ptr, &background, &args, path);
+        }
+        Some((first, last)) => {
+            for frame in first..=last {
+                let time = frame as f64 / args.fps;
+                let shutter_close = time + args.shutter / args.fps;
+                let cam: Arc<dyn Camera> = Arc::new(AnimatedCamera::construct(
+                    &camera_track,
+                    &camera_builder,
+                    time,
+                    shutter_close,
+                ));
+                let frame_path = numbered_path(path, frame);
+                render(
+                    cam,
+                    &world,
+                    lights_ptr.clone(),
+                    &background,
+                    &args,
+                    &frame_path,
+                );
             }
-            assert_eq!(cnt, SAMPLES_PER_PIXEL);
-            let rgb: [u8; 3] = write_color(&pixel_color, SAMPLES_PER_PIXEL);
-            *pixel = image::Rgb(rgb);
-            progress.inc(1);
         }
     }
-    progress.finish();
-
-    println!(
-        "Output image as \"{}\"",
-        style(path.to_str().unwrap()).yellow()
-    );
-    let output_image = image::DynamicImage::ImageRgb8(img);
-    let mut output_file = File::create(path).unwrap();
-    match output_image.write_to(&mut output_file, image::ImageOutputFormat::Jpeg(quality)) {
-        Ok(_) => {}
-        Err(_) => println!("{}", style("Outputing image fails.").red()),
-    }
 
     exit(0);
 }
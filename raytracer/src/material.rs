@@ -1,11 +1,13 @@
 use crate::hittable::HitRecord;
-use crate::pdf::{CosinePdf, Pdf};
+use crate::pdf::{henyey_greenstein, CosinePdf, HenyeyGreensteinPdf, Pdf};
 use crate::ray::Ray;
-use crate::rt_weekend::{random_double, PI};
+use crate::rt_weekend::{clamp, hash_double, random_double, PI};
+use crate::spectrum;
 use crate::texture::{SolidColor, Texture};
 use crate::vec3::{dot, random_in_unit_sphere, reflect, refract, Color3, Point3, Vec3};
 // use std::ops::Deref;
 use std::fmt::Debug;
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Arc;
 
 pub trait Material: Debug + Send + Sync {
@@ -18,12 +20,23 @@ pub trait Material: Debug + Send + Sync {
     fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
         0.0
     }
+    // Nonzero ID for ID mattes.
+    fn id(&self) -> u32;
+}
+
+static NEXT_MATERIAL_ID: AtomicU32 = AtomicU32::new(1);
+
+// IDs count up from 1 in construction order, so a scene built the same way
+// gets the same IDs on every run.
+pub fn next_material_id() -> u32 {
+    NEXT_MATERIAL_ID.fetch_add(1, Ordering::Relaxed)
 }
 
 #[derive(Debug)]
 pub struct Lambertian {
     // pub albedo: Color3,
     pub albedo: Arc<dyn Texture>,
+    id: u32,
 }
 
 impl Lambertian {
@@ -35,11 +48,13 @@ impl Lambertian {
     pub fn construct(a: &Color3) -> Self {
         Self {
             albedo: Arc::new(SolidColor::construct(a)),
+            id: next_material_id(),
         }
     }
     pub fn construct_texture(a: Arc<dyn Texture>) -> Self {
         Self {
             albedo: Arc::clone(&a),
+            id: next_material_id(),
         }
     }
 }
@@ -57,24 +72,29 @@ impl Material for Lambertian {
         // *pdf = 0.5 / PI;
 
         srec.is_specular = false;
-        srec.attenuation = self.albedo.value(rec.u, rec.v, &rec.p);
-        srec.pdf_ptr = Some(Arc::new(CosinePdf::construct(&rec.normal)));
+        srec.attenuation = self.albedo.value_at(rec);
+        srec.pdf_ptr = Some(Arc::new(CosinePdf::construct(&rec.shading_normal)));
         true
     }
     fn scattering_pdf(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
-        let cosine: f64 = dot(&rec.normal, &scattered.direction().unit());
+        let cosine: f64 = dot(&rec.shading_normal, &scattered.direction().unit());
         if cosine < 0.0 {
             0.0
         } else {
             cosine / PI
         }
     }
+    fn id(&self) -> u32 {
+        self.id
+    }
 }
 
 #[derive(Debug)]
 pub struct Metal {
     albedo: Color3,
     fuzz: f64,
+    thin_film: Option<ThinFilm>,
+    id: u32,
 }
 
 impl Metal {
@@ -88,36 +108,264 @@ impl Metal {
         Self {
             albedo: *albedo,
             fuzz: if fuzz < 1.0 { fuzz } else { 1.0 },
+            thin_film: None,
+            id: next_material_id(),
         }
     }
+    pub fn with_thin_film(mut self, film: ThinFilm) -> Self {
+        self.thin_film = Some(film);
+        self
+    }
 }
 
 impl Material for Metal {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
-        let reflected: Vec3 = reflect(&r_in.direction().unit(), &rec.normal);
+        let reflected: Vec3 = reflect(&r_in.direction().unit(), &rec.shading_normal);
         srec.specular_ray = Ray::construct(
             &rec.p,
             &(reflected + self.fuzz * random_in_unit_sphere()),
-            0.0,
-        );
-        srec.attenuation = self.albedo;
+            r_in.time(),
+        )
+        .with_differential(rec.reflect_differential(r_in));
+        srec.attenuation = match &self.thin_film {
+            Some(film) => {
+                // Treat the albedo as normal-incidence reflectance and recover
+                // the real index that would give it.
+                let cosine = dot(&(-r_in.direction().unit()), &rec.shading_normal);
+                let substrate = ThinFilm::index_from_reflectance(&self.albedo);
+                srec.is_dispersive = r_in.wavelength() > 0.0;
+                film.reflectance(cosine, 1.0, &substrate, r_in, rec)
+            }
+            None => self.albedo,
+        };
         srec.is_specular = true;
         srec.pdf_ptr = None;
         true
     }
+    fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ThinFilm {
+    // Film thickness in nanometres is max_thickness * luminance(thickness).
+    thickness: Arc<dyn Texture>,
+    max_thickness: f64,
+    ir: f64, // Index of Refraction of the film
+}
+impl ThinFilm {
+    // Wavelengths (nm) standing in for the red, green and blue channels.
+    pub const RGB_WAVELENGTHS: [f64; 3] = [650.0, 550.0, 450.0];
+
+    pub fn construct(thickness: f64, ir: f64) -> Self {
+        Self::construct_texture(
+            Arc::new(SolidColor::construct(&Color3::construct(&[1.0, 1.0, 1.0]))),
+            thickness,
+            ir,
+        )
+    }
+    pub fn construct_texture(thickness: Arc<dyn Texture>, max_thickness: f64, ir: f64) -> Self {
+        Self {
+            thickness: Arc::clone(&thickness),
+            max_thickness,
+            ir,
+        }
+    }
+    pub fn index_from_reflectance(f0: &Color3) -> Color3 {
+        let mut n = Color3::new();
+        for i in 0..3 {
+            let r = clamp(f0.e[i], 0.0, 0.99).sqrt();
+            n.e[i] = (1.0 + r) / (1.0 - r);
+        }
+        n
+    }
+
+    fn airy(r12: f64, r23: f64, cos_delta: f64) -> f64 {
+        // |r12 + r23 e^(iδ)|² / |1 + r12 r23 e^(iδ)|²
+        let num = r12 * r12 + r23 * r23 + 2.0 * r12 * r23 * cos_delta;
+        let den = 1.0 + r12 * r12 * r23 * r23 + 2.0 * r12 * r23 * cos_delta;
+        num / den
+    }
+
+    // Reflectance for each RGB channel, or the same value in every channel
+    // for a ray that carries a single wavelength.
+    pub fn reflectance(
+        &self,
+        cos_i: f64,
+        outside: f64,
+        substrate: &Color3,
+        r_in: &Ray,
+        rec: &HitRecord,
+    ) -> Color3 {
+        let thickness = self.max_thickness * self.thickness.value_at(rec).luminance();
+        let wavelength = r_in.wavelength();
+        if wavelength > 0.0 {
+            let n3 = spectrum::rgb_to_spectrum(substrate, wavelength);
+            let f = self.reflectance_at(cos_i, outside, n3, thickness, wavelength);
+            return Color3::construct(&[f, f, f]);
+        }
+        let mut result = Color3::new();
+        for i in 0..3 {
+            result.e[i] = self.reflectance_at(
+                cos_i,
+                outside,
+                substrate.e[i],
+                thickness,
+                Self::RGB_WAVELENGTHS[i],
+            );
+        }
+        result
+    }
+    fn reflectance_at(&self, cos_i: f64, n1: f64, n3: f64, thickness: f64, wavelength: f64) -> f64 {
+        let n2 = self.ir;
+        let cos1 = clamp(cos_i, 0.0, 1.0);
+        let sin1_sq = 1.0 - cos1 * cos1;
+        let sin2_sq = n1 * n1 / (n2 * n2) * sin1_sq;
+        let sin3_sq = n1 * n1 / (n3 * n3) * sin1_sq;
+        if sin2_sq >= 1.0 || sin3_sq >= 1.0 {
+            return 1.0;
+        }
+        let cos2 = (1.0 - sin2_sq).sqrt();
+        let cos3 = (1.0 - sin3_sq).sqrt();
+
+        let r12_s = (n1 * cos1 - n2 * cos2) / (n1 * cos1 + n2 * cos2);
+        let r12_p = (n2 * cos1 - n1 * cos2) / (n2 * cos1 + n1 * cos2);
+        let r23_s = (n2 * cos2 - n3 * cos3) / (n2 * cos2 + n3 * cos3);
+        let r23_p = (n3 * cos2 - n2 * cos3) / (n3 * cos2 + n2 * cos3);
+
+        let delta = 4.0 * PI * n2 * thickness * cos2 / wavelength;
+        let cos_delta = delta.cos();
+        0.5 * (Self::airy(r12_s, r23_s, cos_delta) + Self::airy(r12_p, r23_p, cos_delta))
+    }
+}
+
+#[derive(Debug)]
+pub struct Sheen {
+    color: Arc<dyn Texture>,
+    roughness: f64,
+    id: u32,
+}
+impl Sheen {
+    pub fn construct(color: Arc<dyn Texture>, roughness: f64) -> Self {
+        Self {
+            color: Arc::clone(&color),
+            roughness: clamp(roughness, 0.01, 1.0),
+            id: next_material_id(),
+        }
+    }
+    pub fn construct_color(color: &Color3, roughness: f64) -> Self {
+        Self::construct(Arc::new(SolidColor::construct(color)), roughness)
+    }
+}
+impl Material for Sheen {
+    fn scatter(&self, _r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+        srec.is_specular = false;
+        srec.attenuation = self.color.value_at(rec);
+        srec.pdf_ptr = Some(Arc::new(CosinePdf::construct(&rec.shading_normal)));
+        true
+    }
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        // Charlie distribution (Estevez and Kulla) with Neubelt's visibility term.
+        let wo = -r_in.direction().unit();
+        let wi = scattered.direction().unit();
+        let cos_o = dot(&wo, &rec.shading_normal);
+        let cos_i = dot(&wi, &rec.shading_normal);
+        if cos_o <= 0.0 || cos_i <= 0.0 {
+            return 0.0;
+        }
+        let h = (wo + wi).unit();
+        let cos_h = dot(&h, &rec.shading_normal);
+        let sin_h = (1.0 - cos_h * cos_h).max(0.0).sqrt();
+        let alpha = self.roughness * self.roughness;
+        let d = (2.0 + 1.0 / alpha) * sin_h.powf(1.0 / alpha) / (2.0 * PI);
+        let vis = 1.0 / (4.0 * (cos_i + cos_o - cos_i * cos_o));
+        d * vis * cos_i
+    }
+    fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Dispersion {
+    None,
+    // n(λ) = a + b / λ², with λ in micrometres
+    Cauchy { a: f64, b: f64 },
+    // n²(λ) = 1 + Σ bᵢ λ² / (λ² - cᵢ), with λ in micrometres
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
 }
 
 #[derive(Debug, Clone)]
 pub struct Dielectric {
     ir: f64, // Index of Refraction
+    dispersion: Dispersion,
+    thin_film: Option<ThinFilm>,
+    id: u32,
 }
 
 impl Dielectric {
+    // Sodium d-line, used when a dispersive glass is hit by an RGB ray.
+    pub const REFERENCE_WAVELENGTH: f64 = 587.6;
+
     // pub fn new() -> Self {
     //     Self { ir: 1.0 }
     // }
     pub fn construct(ir: f64) -> Self {
-        Self { ir }
+        Self {
+            ir,
+            dispersion: Dispersion::None,
+            thin_film: None,
+            id: next_material_id(),
+        }
+    }
+    pub fn construct_cauchy(a: f64, b: f64) -> Self {
+        let dispersion = Dispersion::Cauchy { a, b };
+        Self {
+            ir: Self::index_for(&dispersion, 0.0, Self::REFERENCE_WAVELENGTH),
+            dispersion,
+            thin_film: None,
+            id: next_material_id(),
+        }
+    }
+    pub fn construct_sellmeier(b: &[f64; 3], c: &[f64; 3]) -> Self {
+        let dispersion = Dispersion::Sellmeier { b: *b, c: *c };
+        Self {
+            ir: Self::index_for(&dispersion, 0.0, Self::REFERENCE_WAVELENGTH),
+            dispersion,
+            thin_film: None,
+            id: next_material_id(),
+        }
+    }
+    pub fn with_thin_film(mut self, film: ThinFilm) -> Self {
+        self.thin_film = Some(film);
+        self
+    }
+
+    fn index_for(dispersion: &Dispersion, ir: f64, wavelength: f64) -> f64 {
+        let lambda = wavelength / 1000.0;
+        let lambda_sq = lambda * lambda;
+        match dispersion {
+            Dispersion::None => ir,
+            Dispersion::Cauchy { a, b } => a + b / lambda_sq,
+            Dispersion::Sellmeier { b, c } => {
+                let mut n_sq = 1.0;
+                for i in 0..3 {
+                    n_sq += b[i] * lambda_sq / (lambda_sq - c[i]);
+                }
+                n_sq.sqrt()
+            }
+        }
+    }
+    pub fn index_of_refraction(&self, wavelength: f64) -> f64 {
+        if wavelength <= 0.0 {
+            self.ir
+        } else {
+            Self::index_for(&self.dispersion, self.ir, wavelength)
+        }
+    }
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self.dispersion, Dispersion::None)
     }
 
     pub fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
@@ -132,44 +380,96 @@ impl Material for Dielectric {
         srec.is_specular = true;
         srec.pdf_ptr = None;
         srec.attenuation = Color3::construct(&[1.0, 1.0, 1.0]);
-        let refraction_ratio = if rec.front_face {
-            1.0 / self.ir
-        } else {
-            self.ir
-        };
+        srec.is_dispersive = self.is_dispersive() && r_in.wavelength() > 0.0;
+        let ir = self.index_of_refraction(r_in.wavelength());
+        let refraction_ratio = if rec.front_face { 1.0 / ir } else { ir };
         let unit_direction = r_in.direction().unit();
-        let cos_theta: f64 = dot(&(-unit_direction), &rec.normal).min(1.0);
+        let cos_theta: f64 = dot(&(-unit_direction), &rec.shading_normal).min(1.0);
         let sin_theta: f64 = (1.0 - cos_theta * cos_theta).sqrt();
 
         let cannot_refract: bool = refraction_ratio * sin_theta > 1.0;
 
-        let direction: Vec3 =
+        if let Some(film) = &self.thin_film {
+            // Colored reflectance: pick a branch with the average probability
+            // and reweight each channel accordingly.
+            let (outside, inside) = if rec.front_face { (1.0, ir) } else { (ir, 1.0) };
+            let substrate = Color3::construct(&[inside, inside, inside]);
+            let f = film.reflectance(cos_theta, outside, &substrate, r_in, rec);
+            srec.is_dispersive = r_in.wavelength() > 0.0;
+            let f_avg = (f.x() + f.y() + f.z()) / 3.0;
+            let (direction, differential) = if cannot_refract {
+                (
+                    reflect(&unit_direction, &rec.shading_normal),
+                    rec.reflect_differential(r_in),
+                )
+            } else if random_double() < f_avg {
+                srec.attenuation = f / f_avg;
+                (
+                    reflect(&unit_direction, &rec.shading_normal),
+                    rec.reflect_differential(r_in),
+                )
+            } else {
+                srec.attenuation = (Color3::construct(&[1.0, 1.0, 1.0]) - f) / (1.0 - f_avg);
+                (
+                    refract(&unit_direction, &rec.shading_normal, refraction_ratio),
+                    rec.refract_differential(r_in, refraction_ratio),
+                )
+            };
+            srec.specular_ray =
+                Ray::construct(&rec.p, &direction, r_in.time()).with_differential(differential);
+            return true;
+        }
+
+        let (direction, differential) =
             if cannot_refract || Self::reflectance(cos_theta, refraction_ratio) > random_double() {
-                reflect(&unit_direction, &rec.normal)
+                (
+                    reflect(&unit_direction, &rec.shading_normal),
+                    rec.reflect_differential(r_in),
+                )
             } else {
-                refract(&unit_direction, &rec.normal, refraction_ratio)
+                (
+                    refract(&unit_direction, &rec.shading_normal, refraction_ratio),
+                    rec.refract_differential(r_in, refraction_ratio),
+                )
             };
 
-        srec.specular_ray = Ray::construct(&rec.p, &direction, r_in.time());
+        srec.specular_ray =
+            Ray::construct(&rec.p, &direction, r_in.time()).with_differential(differential);
         true
     }
+    fn id(&self) -> u32 {
+        self.id
+    }
 }
 
 #[derive(Debug)]
 pub struct DiffuseLight {
     emit: Arc<dyn Texture>,
+    scale: f64,
+    two_sided: bool,
+    id: u32,
 }
 impl DiffuseLight {
-    // pub fn construct(emit: Arc<dyn Texture>) -> Self {
-    //     Self {
-    //         emit: Arc::clone(&emit),
-    //     }
-    // }
-    pub fn construct_color(emit: &Color3) -> Self {
+    pub fn construct(emit: Arc<dyn Texture>) -> Self {
         Self {
-            emit: Arc::new(SolidColor::construct(emit)),
+            emit: Arc::clone(&emit),
+            scale: 1.0,
+            two_sided: false,
+            id: next_material_id(),
         }
     }
+    pub fn construct_color(emit: &Color3) -> Self {
+        Self::construct(Arc::new(SolidColor::construct(emit)))
+    }
+    pub fn with_scale(mut self, scale: f64) -> Self {
+        self.scale = scale;
+        self
+    }
+    pub fn with_two_sided(mut self, two_sided: bool) -> Self {
+        // One-sided lights only emit on the side their outward normal faces.
+        self.two_sided = two_sided;
+        self
+    }
 }
 impl Material for DiffuseLight {
     // fn scatter(
@@ -182,12 +482,278 @@ impl Material for DiffuseLight {
     //     false
     // }
     fn emitted(&self, _r_in: &Ray, rec: &HitRecord, u: f64, v: f64, p: &Point3) -> Color3 {
-        if rec.front_face {
-            self.emit.value(u, v, &p)
+        if rec.front_face || self.two_sided {
+            self.scale * self.emit.value(u, v, p)
         } else {
             Vec3::construct(&[0.0, 0.0, 0.0])
         }
     }
+    fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+#[derive(Debug)]
+pub struct NormalMap {
+    base: Arc<dyn Material>,
+    map: Arc<dyn Texture>,
+    strength: f64,
+    id: u32,
+}
+impl NormalMap {
+    pub fn construct(base: Arc<dyn Material>, map: Arc<dyn Texture>, strength: f64) -> Self {
+        Self {
+            base: Arc::clone(&base),
+            map: Arc::clone(&map),
+            strength,
+            id: next_material_id(),
+        }
+    }
+    fn perturb(&self, rec: &HitRecord) -> HitRecord {
+        // Tangent-space normal stored as RGB in [0, 1].
+        let c = self.map.value_at(rec);
+        let x = self.strength * (2.0 * c.x() - 1.0);
+        let y = self.strength * (2.0 * c.y() - 1.0);
+        let z = 2.0 * c.z() - 1.0;
+        let mut perturbed = rec.clone();
+        perturbed
+            .set_shading_normal(&(x * rec.tangent + y * rec.bitangent + z * rec.shading_normal));
+        perturbed
+    }
+}
+impl Material for NormalMap {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+        self.base.scatter(r_in, &self.perturb(rec), srec)
+    }
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord, u: f64, v: f64, p: &Point3) -> Color3 {
+        self.base.emitted(r_in, &self.perturb(rec), u, v, p)
+    }
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        self.base
+            .scattering_pdf(r_in, &self.perturb(rec), scattered)
+    }
+    fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+#[derive(Debug)]
+pub struct BumpMap {
+    base: Arc<dyn Material>,
+    height: Arc<dyn Texture>,
+    scale: f64,
+    delta: f64,
+    id: u32,
+}
+impl BumpMap {
+    pub fn construct(base: Arc<dyn Material>, height: Arc<dyn Texture>, scale: f64) -> Self {
+        Self {
+            base: Arc::clone(&base),
+            height: Arc::clone(&height),
+            scale,
+            delta: 0.001,
+            id: next_material_id(),
+        }
+    }
+    pub fn with_delta(mut self, delta: f64) -> Self {
+        // Finite-difference step, applied both in uv and along the tangent frame
+        // so that image and solid textures can drive the bump.
+        self.delta = delta;
+        self
+    }
+    fn perturb(&self, rec: &HitRecord) -> HitRecord {
+        let h = self.height.value(rec.u, rec.v, &rec.p).luminance();
+        let h_u = self
+            .height
+            .value(
+                rec.u + self.delta,
+                rec.v,
+                &(rec.p + self.delta * rec.tangent),
+            )
+            .luminance();
+        let h_v = self
+            .height
+            .value(
+                rec.u,
+                rec.v + self.delta,
+                &(rec.p + self.delta * rec.bitangent),
+            )
+            .luminance();
+        let dh_du = self.scale * (h_u - h) / self.delta;
+        let dh_dv = self.scale * (h_v - h) / self.delta;
+        let mut perturbed = rec.clone();
+        perturbed.set_shading_normal(
+            &(rec.shading_normal - dh_du * rec.tangent - dh_dv * rec.bitangent),
+        );
+        perturbed
+    }
+}
+impl Material for BumpMap {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+        self.base.scatter(r_in, &self.perturb(rec), srec)
+    }
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord, u: f64, v: f64, p: &Point3) -> Color3 {
+        self.base.emitted(r_in, &self.perturb(rec), u, v, p)
+    }
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        self.base
+            .scattering_pdf(r_in, &self.perturb(rec), scattered)
+    }
+    fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+#[derive(Debug)]
+pub struct MixMaterial {
+    a: Arc<dyn Material>,
+    b: Arc<dyn Material>,
+    // Probability of using `b`, read as the luminance of the texture.
+    weight: Arc<dyn Texture>,
+    id: u32,
+}
+impl MixMaterial {
+    pub fn construct(a: Arc<dyn Material>, b: Arc<dyn Material>, weight: Arc<dyn Texture>) -> Self {
+        Self {
+            a: Arc::clone(&a),
+            b: Arc::clone(&b),
+            weight: Arc::clone(&weight),
+            id: next_material_id(),
+        }
+    }
+    pub fn construct_weight(a: Arc<dyn Material>, b: Arc<dyn Material>, weight: f64) -> Self {
+        Self::construct(
+            a,
+            b,
+            Arc::new(SolidColor::construct(&Color3::construct(&[
+                weight, weight, weight,
+            ]))),
+        )
+    }
+    fn weight_at(&self, rec: &HitRecord) -> f64 {
+        clamp(self.weight.value_at(rec).luminance(), 0.0, 1.0)
+    }
+    fn choose(&self, r_in: &Ray, rec: &HitRecord) -> &Arc<dyn Material> {
+        // scatter() and scattering_pdf() are called separately for the same
+        // hit, so the choice is hashed from the hit instead of drawn at random.
+        let d = r_in.direction();
+        let xi = hash_double(&[rec.p.x(), rec.p.y(), rec.p.z(), d.x(), d.y(), d.z()]);
+        if xi < self.weight_at(rec) {
+            &self.b
+        } else {
+            &self.a
+        }
+    }
+}
+impl Material for MixMaterial {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+        self.choose(r_in, rec).scatter(r_in, rec, srec)
+    }
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord, u: f64, v: f64, p: &Point3) -> Color3 {
+        let w = self.weight_at(rec);
+        (1.0 - w) * self.a.emitted(r_in, rec, u, v, p) + w * self.b.emitted(r_in, rec, u, v, p)
+    }
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        self.choose(r_in, rec).scattering_pdf(r_in, rec, scattered)
+    }
+    fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+#[derive(Debug)]
+pub struct Coated {
+    base: Arc<dyn Material>,
+    ir: f64, // Index of Refraction of the clear coat
+    id: u32,
+}
+impl Coated {
+    pub fn construct(base: Arc<dyn Material>, ir: f64) -> Self {
+        Self {
+            base: Arc::clone(&base),
+            ir,
+            id: next_material_id(),
+        }
+    }
+    fn fresnel(&self, rec: &HitRecord, direction: &Vec3) -> f64 {
+        let cosine = dot(&direction.unit(), &rec.shading_normal).abs().min(1.0);
+        Dielectric::reflectance(cosine, self.ir)
+    }
+}
+impl Material for Coated {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+        // Reflect off the coat with probability F, which cancels the Fresnel
+        // weight; otherwise the transmitted (1 - F) share reaches the base.
+        let unit_direction = r_in.direction().unit();
+        if random_double() < self.fresnel(rec, &unit_direction) {
+            srec.is_specular = true;
+            srec.pdf_ptr = None;
+            srec.attenuation = Color3::construct(&[1.0, 1.0, 1.0]);
+            srec.specular_ray = Ray::construct(
+                &rec.p,
+                &reflect(&unit_direction, &rec.shading_normal),
+                r_in.time(),
+            )
+            .with_differential(rec.reflect_differential(r_in));
+            return true;
+        }
+        if !self.base.scatter(r_in, rec, srec) {
+            return false;
+        }
+        if srec.is_specular {
+            // Diffuse bases pay the exit Fresnel term in scattering_pdf instead.
+            let exit = srec.specular_ray.direction();
+            srec.attenuation *= 1.0 - self.fresnel(rec, &exit);
+        }
+        true
+    }
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord, u: f64, v: f64, p: &Point3) -> Color3 {
+        self.base.emitted(r_in, rec, u, v, p)
+    }
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        (1.0 - self.fresnel(rec, &scattered.direction()))
+            * self.base.scattering_pdf(r_in, rec, scattered)
+    }
+    fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+#[derive(Debug)]
+pub struct HenyeyGreenstein {
+    albedo: Arc<dyn Texture>,
+    g: f64, // Anisotropy in (-1, 1); 0 is isotropic
+    id: u32,
+}
+impl HenyeyGreenstein {
+    pub fn construct(albedo: Arc<dyn Texture>, g: f64) -> Self {
+        Self {
+            albedo: Arc::clone(&albedo),
+            g,
+            id: next_material_id(),
+        }
+    }
+    pub fn construct_color(albedo: &Color3, g: f64) -> Self {
+        Self::construct(Arc::new(SolidColor::construct(albedo)), g)
+    }
+}
+impl Material for HenyeyGreenstein {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+        srec.is_specular = false;
+        srec.attenuation = self.albedo.value_at(rec);
+        srec.pdf_ptr = Some(Arc::new(HenyeyGreensteinPdf::construct(
+            &r_in.direction(),
+            self.g,
+        )));
+        true
+    }
+    fn scattering_pdf(&self, r_in: &Ray, _rec: &HitRecord, scattered: &Ray) -> f64 {
+        let cosine = dot(&r_in.direction().unit(), &scattered.direction().unit());
+        henyey_greenstein(cosine, self.g)
+    }
+    fn id(&self) -> u32 {
+        self.id
+    }
 }
 
 // pub struct Isotropic {
@@ -224,6 +790,11 @@ pub struct ScatterRecord {
     pub is_specular: bool,
     pub attenuation: Color3,
     pub pdf_ptr: Option<Arc<dyn Pdf>>,
+    // Set when the scattered direction or the attenuation depends on the
+    // ray's wavelength, so only the hero wavelength can carry the estimate.
+    pub is_dispersive: bool,
+    // Set by media that want their lighting sampled through transmittance.
+    pub is_medium: bool,
 }
 impl ScatterRecord {
     pub fn new() -> Self {
@@ -232,6 +803,57 @@ impl ScatterRecord {
             is_specular: false,
             attenuation: Vec3::new(),
             pdf_ptr: None,
+            is_dispersive: false,
+            is_medium: false,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aarect::XzRect;
+    use crate::hittable::Hittable;
+    use crate::texture::{GradientAxis, GradientTexture};
+
+    fn bumped_normal(origin: &Point3, direction: &Vec3) -> Vec3 {
+        // Height rises with v, which runs along +z on an XzRect.
+        let bump = BumpMap::construct(
+            Arc::new(Lambertian::construct(&Color3::construct(&[0.5, 0.5, 0.5]))),
+            Arc::new(GradientTexture::construct(GradientAxis::V, 0.0, 1.0)),
+            0.1,
+        );
+        let rect = XzRect::construct(
+            0.0,
+            1.0,
+            0.0,
+            1.0,
+            0.0,
+            Arc::new(Lambertian::construct(&Color3::new())),
+        );
+        let mut rec = HitRecord::new();
+        assert!(rect.hit(
+            &Ray::construct(origin, direction, 0.0),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+        assert!(dot(&rec.bitangent, &Vec3::construct(&[0.0, 0.0, 1.0])) > 0.99);
+        bump.perturb(&rec).shading_normal
+    }
+
+    #[test]
+    fn bump_gradient_on_xz_rect() {
+        // A slope rising towards +z tilts the normal towards -z on both sides.
+        let front = bumped_normal(
+            &Point3::construct(&[0.5, 1.0, 0.5]),
+            &Vec3::construct(&[0.0, -1.0, 0.0]),
+        );
+        assert!(front.y() > 0.0 && front.z() < -0.05);
+        let back = bumped_normal(
+            &Point3::construct(&[0.5, -1.0, 0.5]),
+            &Vec3::construct(&[0.0, 1.0, 0.0]),
+        );
+        assert!(back.y() < 0.0 && back.z() < -0.05);
+    }
+}
@@ -38,21 +38,23 @@ impl Pdf for CosinePdf {
 pub struct HittablePdf {
     pub o: Point3,
     pub ptr: Arc<dyn Hittable>,
+    pub time: f64,
 }
 impl HittablePdf {
-    pub fn construct(p: Arc<dyn Hittable>, origin: &Point3) -> Self {
+    pub fn construct(p: Arc<dyn Hittable>, origin: &Point3, time: f64) -> Self {
         Self {
             o: *origin,
             ptr: Arc::clone(&p),
+            time,
         }
     }
 }
 impl Pdf for HittablePdf {
     fn value(&self, direction: &Vec3) -> f64 {
-        self.ptr.pdf_value(&self.o, direction)
+        self.ptr.pdf_value(&self.o, direction, self.time)
     }
     fn generate(&self) -> Vec3 {
-        self.ptr.random(&self.o)
+        self.ptr.random(&self.o, self.time)
     }
 }
 
@@ -89,3 +91,42 @@ pub fn random_to_sphere(radius: f64, distance_sq: f64) -> Vec3 {
     let y = phi.sin() * (1.0 - z * z).sqrt();
     Vec3::construct(&[x, y, z])
 }
+
+pub fn henyey_greenstein(cos_theta: f64, g: f64) -> f64 {
+    // Phase function value; cos_theta is measured from the propagation direction.
+    let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+    (1.0 - g * g) / (4.0 * PI * denom * denom.sqrt())
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HenyeyGreensteinPdf {
+    pub uvw: Onb,
+    pub g: f64,
+}
+impl HenyeyGreensteinPdf {
+    pub fn construct(forward: &Vec3, g: f64) -> Self {
+        Self {
+            uvw: Onb::build_from_w(forward),
+            g,
+        }
+    }
+}
+impl Pdf for HenyeyGreensteinPdf {
+    fn value(&self, direction: &Vec3) -> f64 {
+        henyey_greenstein(dot(&direction.unit(), &self.uvw.w()), self.g)
+    }
+    fn generate(&self) -> Vec3 {
+        let g = self.g;
+        let xi = random_double();
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * xi
+        } else {
+            let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * xi);
+            (1.0 + g * g - s * s) / (2.0 * g)
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * random_double();
+        self.uvw
+            .local_f64(phi.cos() * sin_theta, phi.sin() * sin_theta, cos_theta)
+    }
+}
@@ -1,73 +1,223 @@
-use crate::rt_weekend::random_int;
 use crate::vec3::{dot, Point3, Vec3};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use std::vec::Vec;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fade {
+    // 3t^2 - 2t^3, as in the original book.
+    Hermite,
+    // 6t^5 - 15t^4 + 10t^3 from Perlin's improved noise; C2 continuous.
+    Quintic,
+}
+impl Fade {
+    fn apply(&self, t: f64) -> f64 {
+        match self {
+            Fade::Hermite => t * t * (3.0 - 2.0 * t),
+            Fade::Quintic => t * t * t * (t * (t * 6.0 - 15.0) + 10.0),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Perlin {
     pub ranvec: Vec<Vec3>,
+    pub ranvec4: Vec<[f64; 4]>,
     pub perm_x: Vec<u32>,
     pub perm_y: Vec<u32>,
     pub perm_z: Vec<u32>,
+    pub perm_w: Vec<u32>,
+    pub fade: Fade,
+    pub octaves: u32,
 }
 impl Perlin {
     const POINT_COUNT: u32 = 256;
     pub fn new() -> Self {
+        Self::construct(rand::random::<u64>())
+    }
+    pub fn construct(seed: u64) -> Self {
+        // All tables come from one seeded generator, so the same seed always
+        // gives the same noise.
+        let mut rng = StdRng::seed_from_u64(seed);
         let mut ranvec: Vec<Vec3> = Vec::with_capacity(Self::POINT_COUNT as usize);
+        let mut ranvec4: Vec<[f64; 4]> = Vec::with_capacity(Self::POINT_COUNT as usize);
         for _i in 0..Self::POINT_COUNT {
-            ranvec.push(Vec3::random_range(-1.0, 1.0).unit());
+            ranvec.push(Self::random_unit(&mut rng));
+            ranvec4.push(Self::random_unit4(&mut rng));
         }
         Self {
             ranvec,
-            perm_x: Self::perlin_generate_perm(),
-            perm_y: Self::perlin_generate_perm(),
-            perm_z: Self::perlin_generate_perm(),
+            ranvec4,
+            perm_x: Self::perlin_generate_perm(&mut rng),
+            perm_y: Self::perlin_generate_perm(&mut rng),
+            perm_z: Self::perlin_generate_perm(&mut rng),
+            perm_w: Self::perlin_generate_perm(&mut rng),
+            fade: Fade::Hermite,
+            octaves: 7,
         }
     }
+    pub fn with_fade(mut self, fade: Fade) -> Self {
+        self.fade = fade;
+        self
+    }
+    pub fn with_octaves(mut self, octaves: u32) -> Self {
+        self.octaves = octaves;
+        self
+    }
+
+    fn random_unit(rng: &mut StdRng) -> Vec3 {
+        loop {
+            let v = Vec3::construct(&[
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+            ]);
+            let len = v.length();
+            if len > 1e-6 && len <= 1.0 {
+                return v / len;
+            }
+        }
+    }
+    fn random_unit4(rng: &mut StdRng) -> [f64; 4] {
+        loop {
+            let mut v = [0.0; 4];
+            for x in v.iter_mut() {
+                *x = rng.gen_range(-1.0..1.0);
+            }
+            let len = v.iter().map(|x| x * x).sum::<f64>().sqrt();
+            if len > 1e-6 && len <= 1.0 {
+                for x in v.iter_mut() {
+                    *x /= len;
+                }
+                return v;
+            }
+        }
+    }
+
+    fn hash(&self, i: i32, j: i32, k: i32) -> usize {
+        (self.perm_x[(i & 255) as usize]
+            ^ self.perm_y[(j & 255) as usize]
+            ^ self.perm_z[(k & 255) as usize]) as usize
+    }
     pub fn noise(&self, p: &Point3) -> f64 {
+        // The fade is applied once, in perlin_interp.
         let u: f64 = p.x() - p.x().floor();
         let v: f64 = p.y() - p.y().floor();
         let w: f64 = p.z() - p.z().floor();
-        let u = u * u * (3.0 - 2.0 * u);
-        let v = v * v * (3.0 - 2.0 * v);
-        let w = w * w * (3.0 - 2.0 * w);
 
         let i: i32 = p.x().floor() as i32;
         let j: i32 = p.y().floor() as i32;
         let k: i32 = p.z().floor() as i32;
         let mut c: [[[Vec3; 2]; 2]; 2] = [[[Vec3::new(); 2]; 2]; 2];
 
-        for di in 0..2 {
-            for dj in 0..2 {
-                for dk in 0..2 {
-                    c[di as usize][dj as usize][dk as usize] = self.ranvec[(self.perm_x
-                        [((i + di as i32) & 255) as usize]
-                        ^ self.perm_y[((j + dj as i32) & 255) as usize]
-                        ^ self.perm_z[((k + dk as i32) & 255) as usize])
-                        as usize];
+        for (di, plane) in c.iter_mut().enumerate() {
+            for (dj, row) in plane.iter_mut().enumerate() {
+                for (dk, corner) in row.iter_mut().enumerate() {
+                    *corner = self.ranvec[self.hash(i + di as i32, j + dj as i32, k + dk as i32)];
                 }
             }
         }
 
-        Self::perlin_interp(&c, u, v, w)
+        self.perlin_interp(&c, u, v, w)
     }
-    fn perlin_generate_perm() -> Vec<u32> {
+    pub fn noise4(&self, p: &Point3, time: f64) -> f64 {
+        // Gradient noise over (x, y, z, t), for patterns that evolve over
+        // the shutter interval without sliding through space.
+        let x = [p.x(), p.y(), p.z(), time];
+        let mut cell = [0i32; 4];
+        let mut f = [0.0; 4];
+        let mut s = [0.0; 4];
+        for a in 0..4 {
+            cell[a] = x[a].floor() as i32;
+            f[a] = x[a] - x[a].floor();
+            s[a] = self.fade.apply(f[a]);
+        }
+        let mut accum = 0.0;
+        for corner in 0..16 {
+            let d = [
+                corner & 1,
+                (corner >> 1) & 1,
+                (corner >> 2) & 1,
+                (corner >> 3) & 1,
+            ];
+            let index = self.hash(cell[0] + d[0], cell[1] + d[1], cell[2] + d[2])
+                ^ self.perm_w[((cell[3] + d[3]) & 255) as usize] as usize;
+            let g = &self.ranvec4[index];
+            let mut weight = 1.0;
+            let mut grad = 0.0;
+            for a in 0..4 {
+                let offset = f[a] - d[a] as f64;
+                grad += g[a] * offset;
+                weight *= if d[a] == 1 { s[a] } else { 1.0 - s[a] };
+            }
+            accum += weight * grad;
+        }
+        accum
+    }
+    pub fn simplex(&self, p: &Point3) -> f64 {
+        // 3D simplex noise (Gustavson's formulation), roughly in [-1, 1].
+        const F3: f64 = 1.0 / 3.0;
+        const G3: f64 = 1.0 / 6.0;
+        let skew = (p.x() + p.y() + p.z()) * F3;
+        let i = (p.x() + skew).floor();
+        let j = (p.y() + skew).floor();
+        let k = (p.z() + skew).floor();
+        let unskew = (i + j + k) * G3;
+        let x0 = Vec3::construct(&[
+            p.x() - (i - unskew),
+            p.y() - (j - unskew),
+            p.z() - (k - unskew),
+        ]);
+
+        // Which of the six tetrahedra of the skewed cube we are in.
+        let (o1, o2) = if x0.x() >= x0.y() {
+            if x0.y() >= x0.z() {
+                ([1, 0, 0], [1, 1, 0])
+            } else if x0.x() >= x0.z() {
+                ([1, 0, 0], [1, 0, 1])
+            } else {
+                ([0, 0, 1], [1, 0, 1])
+            }
+        } else if x0.y() < x0.z() {
+            ([0, 0, 1], [0, 1, 1])
+        } else if x0.x() < x0.z() {
+            ([0, 1, 0], [0, 1, 1])
+        } else {
+            ([0, 1, 0], [1, 1, 0])
+        };
+        let corners = [[0, 0, 0], o1, o2, [1, 1, 1]];
+        let (i, j, k) = (i as i32, j as i32, k as i32);
+
+        let mut accum = 0.0;
+        for (n, o) in corners.iter().enumerate() {
+            let g = G3 * n as f64;
+            let x = x0 - Vec3::construct(&[o[0] as f64 - g, o[1] as f64 - g, o[2] as f64 - g]);
+            let t = 0.6 - x.length_squared();
+            if t > 0.0 {
+                let gradient = self.ranvec[self.hash(i + o[0], j + o[1], k + o[2])];
+                accum += t * t * t * t * dot(&gradient, &x);
+            }
+        }
+        32.0 * accum
+    }
+    fn perlin_generate_perm(rng: &mut StdRng) -> Vec<u32> {
         let mut p: Vec<u32> = Vec::with_capacity(Self::POINT_COUNT as usize);
         for i in 0..Self::POINT_COUNT {
             p.push(i);
         }
-        Self::permute(&mut p, Self::POINT_COUNT);
+        Self::permute(rng, &mut p, Self::POINT_COUNT);
         p
     }
-    fn permute(p: &mut [u32], n: u32) {
+    fn permute(rng: &mut StdRng, p: &mut [u32], n: u32) {
         for i in (1..n).rev() {
-            let target = random_int(0, i);
+            let target = rng.gen_range(0..=i);
             p.swap(i as usize, target as usize);
         }
     }
-    fn perlin_interp(c: &[[[Vec3; 2]; 2]; 2], u: f64, v: f64, w: f64) -> f64 {
-        let uu = u * u * (3.0 - 2.0 * u);
-        let vv = v * v * (3.0 - 2.0 * v);
-        let ww = w * w * (3.0 - 2.0 * w);
+    fn perlin_interp(&self, c: &[[[Vec3; 2]; 2]; 2], u: f64, v: f64, w: f64) -> f64 {
+        let uu = self.fade.apply(u);
+        let vv = self.fade.apply(v);
+        let ww = self.fade.apply(w);
         let mut accum = 0.0;
         for i in c.iter().enumerate() {
             for j in c[i.0].iter().enumerate() {
@@ -84,16 +234,29 @@ impl Perlin {
         accum
     }
     pub fn turb(&self, p: &Point3) -> f64 {
-        let depth: u32 = 7;
         let mut accum = 0.0;
         let mut temp_p = *p;
         let mut weight = 1.0;
 
-        for _i in 0..depth {
+        for _i in 0..self.octaves {
             accum += weight * self.noise(&temp_p);
             weight *= 0.5;
             temp_p *= 2.0;
         }
         accum.abs()
     }
+    pub fn turb4(&self, p: &Point3, time: f64) -> f64 {
+        let mut accum = 0.0;
+        let mut temp_p = *p;
+        let mut temp_t = time;
+        let mut weight = 1.0;
+
+        for _i in 0..self.octaves {
+            accum += weight * self.noise4(&temp_p, temp_t);
+            weight *= 0.5;
+            temp_p *= 2.0;
+            temp_t *= 2.0;
+        }
+        accum.abs()
+    }
 }
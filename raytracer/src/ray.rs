@@ -2,11 +2,22 @@
 use crate::vec3::Point3;
 use crate::vec3::Vec3;
 
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RayDifferential {
+    // Auxiliary rays offset by one pixel in x and in y.
+    pub rx_origin: Point3,
+    pub rx_direction: Vec3,
+    pub ry_origin: Point3,
+    pub ry_direction: Vec3,
+}
+
 #[derive(Clone, Copy, Debug, Default)]
 pub struct Ray {
     origin: Point3,
     direction: Vec3,
     tm: f64,
+    wavelength: f64,
+    differential: Option<RayDifferential>,
 }
 
 impl Ray {
@@ -15,6 +26,8 @@ impl Ray {
             origin: Point3::new(),
             direction: Vec3::new(),
             tm: 0.0,
+            wavelength: 0.0,
+            differential: None,
         }
     }
     pub fn construct(origin: &Point3, direction: &Vec3, tm: f64) -> Self {
@@ -22,8 +35,19 @@ impl Ray {
             origin: *origin,
             direction: *direction,
             tm,
+            wavelength: 0.0,
+            differential: None,
         }
     }
+    pub fn with_wavelength(mut self, wavelength: f64) -> Self {
+        // A wavelength of 0.0 means the ray carries RGB radiance.
+        self.wavelength = wavelength;
+        self
+    }
+    pub fn with_differential(mut self, differential: Option<RayDifferential>) -> Self {
+        self.differential = differential;
+        self
+    }
     pub fn at(&self, t: f64) -> Point3 {
         self.origin + self.direction * t
     }
@@ -36,4 +60,10 @@ impl Ray {
     pub fn time(&self) -> f64 {
         self.tm
     }
+    pub fn wavelength(&self) -> f64 {
+        self.wavelength
+    }
+    pub fn differential(&self) -> Option<RayDifferential> {
+        self.differential
+    }
 }
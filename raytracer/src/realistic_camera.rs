@@ -0,0 +1,410 @@
+use crate::camera::{camera_basis, Camera};
+use crate::ray::{Ray, RayDifferential};
+use crate::rt_weekend::{random_double, random_double_range};
+use crate::vec3::{dot, Point3, Vec3};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// One refracting interface (or the aperture stop, with zero radius) of a
+// lens prescription. Lengths are in scene units.
+#[derive(Debug, Clone, Copy)]
+pub struct LensElement {
+    pub curvature_radius: f64,
+    // Axial distance to the next interface towards the film.
+    pub thickness: f64,
+    // Index of the medium behind this interface; 0 for the stop.
+    pub eta: f64,
+    pub aperture_radius: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct LensSystem {
+    pub elements: Vec<LensElement>,
+    pub film_diagonal: f64,
+}
+impl LensSystem {
+    pub fn load<P: AsRef<Path>>(
+        path: P,
+        film_diagonal_mm: f64,
+        units_per_meter: f64,
+    ) -> io::Result<Self> {
+        // Whitespace separated "radius thickness ior aperture-diameter" rows
+        // in millimetres, front element first; '#' starts a comment.
+        let text = fs::read_to_string(path)?;
+        let scale = 0.001 * units_per_meter;
+        let mut elements = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let values = line
+                .split_whitespace()
+                .map(|t| t.parse::<f64>())
+                .collect::<Result<Vec<f64>, _>>()
+                .ok()
+                .filter(|v| v.len() == 4)
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Invalid lens element on line {}.", n + 1),
+                    )
+                })?;
+            elements.push(LensElement {
+                curvature_radius: values[0] * scale,
+                thickness: values[1] * scale,
+                eta: values[2],
+                aperture_radius: values[3] * scale / 2.0,
+            });
+        }
+        if elements.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Lens table has no elements.",
+            ));
+        }
+        Ok(Self {
+            elements,
+            film_diagonal: film_diagonal_mm * scale,
+        })
+    }
+}
+
+// Rear-pupil bounds for one band of film radii, sampled along +x.
+#[derive(Debug, Clone, Copy)]
+struct PupilBounds {
+    min: [f64; 2],
+    max: [f64; 2],
+}
+impl PupilBounds {
+    fn area(&self) -> f64 {
+        (self.max[0] - self.min[0]).max(0.0) * (self.max[1] - self.min[1]).max(0.0)
+    }
+}
+
+// Traces camera rays through a real lens prescription. In lens space the
+// film is the z = 0 plane and the elements and the scene lie towards -z;
+// lens x, y, z map to the camera's u, v, w.
+#[derive(Debug, Clone)]
+pub struct RealisticCamera {
+    pub origin: Point3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+    pub lens: LensSystem,
+    pub film_half_width: f64,
+    pub film_half_height: f64,
+    pub time0: f64,
+    pub time1: f64,
+    exit_pupil: Vec<PupilBounds>,
+    max_pupil_area: f64,
+}
+impl RealisticCamera {
+    const PUPIL_BANDS: usize = 64;
+    const PUPIL_GRID: usize = 64;
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        lookfrom: &Point3,
+        lookat: &Point3,
+        vup: &Vec3,
+        lens: LensSystem,
+        aspect_ratio: f64,
+        focus_distance: f64,
+        time0: f64,
+        time1: f64,
+    ) -> Self {
+        let (u, v, w) = camera_basis(lookfrom, lookat, vup);
+        let film_half_width =
+            0.5 * lens.film_diagonal * aspect_ratio / (1.0 + aspect_ratio * aspect_ratio).sqrt();
+        let mut cam = Self {
+            origin: *lookfrom,
+            u,
+            v,
+            w,
+            lens,
+            film_half_width,
+            film_half_height: film_half_width / aspect_ratio,
+            time0,
+            time1,
+            exit_pupil: Vec::new(),
+            max_pupil_area: 0.0,
+        };
+        cam.focus(focus_distance);
+        cam.exit_pupil = (0..Self::PUPIL_BANDS)
+            .map(|i| cam.bound_exit_pupil(i))
+            .collect();
+        cam.max_pupil_area = cam.exit_pupil.iter().map(|b| b.area()).fold(0.0, f64::max);
+        cam
+    }
+
+    fn rear_z(&self) -> f64 {
+        -self.lens.elements.last().unwrap().thickness
+    }
+    fn front_z(&self) -> f64 {
+        -self.lens.elements.iter().map(|e| e.thickness).sum::<f64>()
+    }
+    fn eta_or_air(eta: f64) -> f64 {
+        if eta == 0.0 {
+            1.0
+        } else {
+            eta
+        }
+    }
+
+    fn intersect_element(
+        e: &LensElement,
+        element_z: f64,
+        o: &Point3,
+        d: &Vec3,
+    ) -> Option<(f64, Option<Vec3>)> {
+        if e.curvature_radius == 0.0 {
+            if d.z() == 0.0 {
+                return None;
+            }
+            let t = (element_z - o.z()) / d.z();
+            return if t > 0.0 { Some((t, None)) } else { None };
+        }
+        let center = Point3::construct(&[0.0, 0.0, element_z + e.curvature_radius]);
+        let oc = *o - center;
+        let a = d.length_squared();
+        let half_b = dot(&oc, d);
+        let c = oc.length_squared() - e.curvature_radius * e.curvature_radius;
+        let discriminant = half_b * half_b - a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let sqrtd = discriminant.sqrt();
+        let t0 = (-half_b - sqrtd) / a;
+        let t1 = (-half_b + sqrtd) / a;
+        // The near or far root, depending on which way the surface bulges.
+        let closer = (d.z() > 0.0) ^ (e.curvature_radius < 0.0);
+        let t = if closer { t0.min(t1) } else { t0.max(t1) };
+        if t < 0.0 {
+            return None;
+        }
+        let mut n = (oc + t * *d).unit();
+        if dot(&n, d) > 0.0 {
+            n = -n;
+        }
+        Some((t, Some(n)))
+    }
+    fn refract_through(d: &Vec3, n: &Vec3, eta_ratio: f64) -> Option<Vec3> {
+        let uv = d.unit();
+        let cos_i = dot(&(-uv), n).min(1.0);
+        let sin2_t = eta_ratio * eta_ratio * (1.0 - cos_i * cos_i);
+        if sin2_t >= 1.0 {
+            return None; // total internal reflection
+        }
+        let cos_t = (1.0 - sin2_t).sqrt();
+        Some(eta_ratio * uv + (eta_ratio * cos_i - cos_t) * *n)
+    }
+
+    // Both tracers return None when the ray is blocked by an element's rim,
+    // the stop, or total internal reflection.
+    fn trace_from_film(&self, o: &Point3, d: &Vec3) -> Option<(Point3, Vec3)> {
+        let elements = &self.lens.elements;
+        let (mut o, mut d) = (*o, *d);
+        let mut element_z = 0.0;
+        for i in (0..elements.len()).rev() {
+            let e = &elements[i];
+            element_z -= e.thickness;
+            let (t, n) = Self::intersect_element(e, element_z, &o, &d)?;
+            o += t * d;
+            if o.x() * o.x() + o.y() * o.y() > e.aperture_radius * e.aperture_radius {
+                return None;
+            }
+            if let Some(n) = n {
+                let eta_i = Self::eta_or_air(e.eta);
+                let eta_t = if i > 0 {
+                    Self::eta_or_air(elements[i - 1].eta)
+                } else {
+                    1.0
+                };
+                d = Self::refract_through(&d, &n, eta_i / eta_t)?;
+            }
+        }
+        Some((o, d))
+    }
+    fn trace_from_scene(&self, o: &Point3, d: &Vec3) -> Option<(Point3, Vec3)> {
+        let elements = &self.lens.elements;
+        let (mut o, mut d) = (*o, *d);
+        let mut element_z = self.front_z();
+        for (i, e) in elements.iter().enumerate() {
+            let (t, n) = Self::intersect_element(e, element_z, &o, &d)?;
+            o += t * d;
+            if o.x() * o.x() + o.y() * o.y() > e.aperture_radius * e.aperture_radius {
+                return None;
+            }
+            if let Some(n) = n {
+                let eta_i = if i > 0 {
+                    Self::eta_or_air(elements[i - 1].eta)
+                } else {
+                    1.0
+                };
+                let eta_t = Self::eta_or_air(e.eta);
+                d = Self::refract_through(&d, &n, eta_i / eta_t)?;
+            }
+            element_z += e.thickness;
+        }
+        Some((o, d))
+    }
+
+    fn focus(&mut self, focus_distance: f64) {
+        // Move the lens along the axis (i.e. change the last thickness) until
+        // a paraxial ray from the on-axis point at focus_distance, measured
+        // from the film, converges on the film. Refocusing changes the
+        // effective focal length, which is the lens' focus breathing.
+        let last = self.lens.elements.len() - 1;
+        if self.lens.elements[last].thickness <= 0.0 {
+            self.lens.elements[last].thickness = 0.05 * self.lens.film_diagonal;
+        }
+        let height = 0.01 * self.lens.elements[0].aperture_radius;
+        for _i in 0..32 {
+            let object = Point3::construct(&[0.0, 0.0, -focus_distance]);
+            let target = Point3::construct(&[height, 0.0, self.front_z()]);
+            let (o, d) = match self.trace_from_scene(&object, &(target - object)) {
+                Some(r) => r,
+                None => return,
+            };
+            if d.x() == 0.0 {
+                return;
+            }
+            let image_z = o.z() - o.x() / d.x() * d.z();
+            self.lens.elements[last].thickness =
+                (self.lens.elements[last].thickness + image_z).max(1e-6);
+            if image_z.abs() < 1e-9 {
+                return;
+            }
+        }
+    }
+
+    fn bound_exit_pupil(&self, band: usize) -> PupilBounds {
+        // Trace a grid over the rear element from a few film points in the
+        // band and keep the box of what gets through, grown by one cell.
+        let max_radius = (self.film_half_width * self.film_half_width
+            + self.film_half_height * self.film_half_height)
+            .sqrt();
+        let rear = self.lens.elements.last().unwrap().aperture_radius * 1.5;
+        let rear_z = self.rear_z();
+        let cell = 2.0 * rear / Self::PUPIL_GRID as f64;
+        let mut bounds = PupilBounds {
+            min: [f64::INFINITY; 2],
+            max: [f64::NEG_INFINITY; 2],
+        };
+        for k in 0..3 {
+            let r = max_radius * (band as f64 + k as f64 / 2.0) / Self::PUPIL_BANDS as f64;
+            let film = Point3::construct(&[r, 0.0, 0.0]);
+            for i in 0..Self::PUPIL_GRID {
+                for j in 0..Self::PUPIL_GRID {
+                    let x = -rear + (i as f64 + 0.5) * cell;
+                    let y = -rear + (j as f64 + 0.5) * cell;
+                    let pupil = Point3::construct(&[x, y, rear_z]);
+                    if self.trace_from_film(&film, &(pupil - film)).is_some() {
+                        bounds.min = [bounds.min[0].min(x), bounds.min[1].min(y)];
+                        bounds.max = [bounds.max[0].max(x), bounds.max[1].max(y)];
+                    }
+                }
+            }
+        }
+        if bounds.min[0] > bounds.max[0] {
+            return PupilBounds {
+                min: [0.0; 2],
+                max: [0.0; 2],
+            };
+        }
+        bounds.min = [bounds.min[0] - cell, bounds.min[1] - cell];
+        bounds.max = [bounds.max[0] + cell, bounds.max[1] + cell];
+        bounds
+    }
+}
+impl RealisticCamera {
+    fn film_point(&self, s: f64, t: f64) -> Point3 {
+        // The lens inverts the image, so the film is read rotated by 180°.
+        Point3::construct(&[
+            -(2.0 * s - 1.0) * self.film_half_width,
+            -(2.0 * t - 1.0) * self.film_half_height,
+            0.0,
+        ])
+    }
+    fn sample_pupil(&self, film: &Point3) -> Option<Point3> {
+        let (x, y) = (film.x(), film.y());
+        let radius = (x * x + y * y).sqrt();
+        let max_radius = (self.film_half_width * self.film_half_width
+            + self.film_half_height * self.film_half_height)
+            .sqrt();
+        let band =
+            ((radius / max_radius * Self::PUPIL_BANDS as f64) as usize).min(Self::PUPIL_BANDS - 1);
+        let bounds = &self.exit_pupil[band];
+        let area = bounds.area();
+        // Samples carry no weight, so the pupil-size and cos^4 falloffs are
+        // applied by rejecting rays instead.
+        if area <= 0.0 || random_double() * self.max_pupil_area > area {
+            return None;
+        }
+        let px = bounds.min[0] + random_double() * (bounds.max[0] - bounds.min[0]);
+        let py = bounds.min[1] + random_double() * (bounds.max[1] - bounds.min[1]);
+        let (sin_phi, cos_phi) = if radius > 0.0 {
+            (y / radius, x / radius)
+        } else {
+            (0.0, 1.0)
+        };
+        let pupil = Point3::construct(&[
+            px * cos_phi - py * sin_phi,
+            px * sin_phi + py * cos_phi,
+            self.rear_z(),
+        ]);
+        let cos_theta = -(pupil - *film).unit().z();
+        if random_double() > cos_theta.powi(4) {
+            return None;
+        }
+        Some(pupil)
+    }
+    // World-space origin and direction of the ray from `film` through `pupil`.
+    fn trace_through(&self, film: &Point3, pupil: &Point3) -> Option<(Point3, Vec3)> {
+        let (o, d) = self.trace_from_film(film, &(*pupil - *film))?;
+        Some((
+            self.origin + o.x() * self.u + o.y() * self.v + o.z() * self.w,
+            d.x() * self.u + d.y() * self.v + d.z() * self.w,
+        ))
+    }
+}
+impl Camera for RealisticCamera {
+    fn get_ray(&self, s: f64, t: f64) -> Option<Ray> {
+        let film = self.film_point(s, t);
+        let pupil = self.sample_pupil(&film)?;
+        let (o, d) = self.trace_through(&film, &pupil)?;
+        Some(Ray::construct(
+            &o,
+            &d,
+            random_double_range(self.time0, self.time1),
+        ))
+    }
+    fn get_ray_differential(&self, s: f64, t: f64, ds: f64, dt: f64) -> Option<Ray> {
+        // The neighbouring film points go through the same pupil point, so
+        // the differential measures the footprint of one lens path rather
+        // than the spread between random points on the lens.
+        let film = self.film_point(s, t);
+        let pupil = self.sample_pupil(&film)?;
+        let (o, d) = self.trace_through(&film, &pupil)?;
+        let differential = match (
+            self.trace_through(&self.film_point(s + ds, t), &pupil),
+            self.trace_through(&self.film_point(s, t + dt), &pupil),
+        ) {
+            (Some((rx_origin, rx_direction)), Some((ry_origin, ry_direction))) => {
+                Some(RayDifferential {
+                    rx_origin,
+                    rx_direction,
+                    ry_origin,
+                    ry_direction,
+                })
+            }
+            _ => None,
+        };
+        Some(
+            Ray::construct(&o, &d, random_double_range(self.time0, self.time1))
+                .with_differential(differential),
+        )
+    }
+}
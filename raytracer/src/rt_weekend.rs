@@ -27,3 +27,17 @@ pub fn clamp(x: f64, min: f64, max: f64) -> f64 {
         x
     }
 }
+
+pub fn hash_double(values: &[f64]) -> f64 {
+    // Deterministic value in [0,1) for the given inputs, so that a random
+    // decision can be repeated exactly when the same query comes back.
+    let mut h: u64 = 0x9e37_79b9_7f4a_7c15;
+    for v in values {
+        h ^= v.to_bits();
+        h = h.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        h ^= h >> 31;
+    }
+    (h >> 11) as f64 / (1u64 << 53) as f64
+}
@@ -0,0 +1,85 @@
+use crate::rt_weekend::random_double;
+use crate::vec3::{Color3, Vec3};
+
+pub const LAMBDA_MIN: f64 = 380.0;
+pub const LAMBDA_MAX: f64 = 780.0;
+pub const HERO_COUNT: usize = 4;
+// Integral of the CIE 1931 y-bar curve, used to normalize luminance.
+pub const CIE_Y_INTEGRAL: f64 = 106.856895;
+// Linear sRGB of the equal-energy spectrum, divided out so that a flat
+// spectrum of 1.0 comes back as (1, 1, 1) at the film.
+const EQUAL_ENERGY_RGB: [f64; 3] = [1.201242, 0.950225, 0.908363];
+
+pub type Wavelengths = [f64; HERO_COUNT];
+pub type SpectralSample = [f64; HERO_COUNT];
+
+pub fn sample_wavelengths() -> Wavelengths {
+    // Hero wavelength plus evenly rotated companions.
+    let range = LAMBDA_MAX - LAMBDA_MIN;
+    let hero = LAMBDA_MIN + random_double() * range;
+    let mut lambdas = [0.0; HERO_COUNT];
+    for (i, lambda) in lambdas.iter_mut().enumerate() {
+        let offset = (hero - LAMBDA_MIN) + i as f64 * range / HERO_COUNT as f64;
+        *lambda = LAMBDA_MIN + offset % range;
+    }
+    lambdas
+}
+
+fn piecewise_gaussian(x: f64, mu: f64, sigma1: f64, sigma2: f64) -> f64 {
+    let t = (x - mu) / if x < mu { sigma1 } else { sigma2 };
+    (-0.5 * t * t).exp()
+}
+
+pub fn cie_xyz(lambda: f64) -> Vec3 {
+    // Multi-lobe fit of the CIE 1931 2° observer (Wyman, Sloan and Shirley 2013).
+    let x = 1.056 * piecewise_gaussian(lambda, 599.8, 37.9, 31.0)
+        + 0.362 * piecewise_gaussian(lambda, 442.0, 16.0, 26.7)
+        - 0.065 * piecewise_gaussian(lambda, 501.1, 20.4, 26.2);
+    let y = 0.821 * piecewise_gaussian(lambda, 568.8, 46.9, 40.5)
+        + 0.286 * piecewise_gaussian(lambda, 530.9, 16.3, 31.1);
+    let z = 1.217 * piecewise_gaussian(lambda, 437.0, 11.8, 36.0)
+        + 0.681 * piecewise_gaussian(lambda, 459.0, 26.0, 13.8);
+    Vec3::construct(&[x, y, z])
+}
+
+pub fn xyz_to_linear_srgb(xyz: &Vec3) -> Color3 {
+    Color3::construct(&[
+        3.2404542 * xyz.x() - 1.5371385 * xyz.y() - 0.4985314 * xyz.z(),
+        -0.9692660 * xyz.x() + 1.8760108 * xyz.y() + 0.0415560 * xyz.z(),
+        0.0556434 * xyz.x() - 0.2040259 * xyz.y() + 1.0572252 * xyz.z(),
+    ])
+}
+
+fn sigmoid(lambda: f64, center: f64, width: f64) -> f64 {
+    1.0 / (1.0 + (-(lambda - center) / width).exp())
+}
+
+pub fn rgb_to_spectrum(rgb: &Color3, lambda: f64) -> f64 {
+    // Smooth partition of unity over blue / green / red bands, so white stays
+    // flat and reflectances in [0, 1] stay in [0, 1].
+    let s_bg = sigmoid(lambda, 490.0, 10.0);
+    let s_gr = sigmoid(lambda, 590.0, 10.0);
+    rgb.z() * (1.0 - s_bg) + rgb.y() * (s_bg - s_gr) + rgb.x() * s_gr
+}
+
+pub fn rgb_to_spectral_sample(rgb: &Color3, lambdas: &Wavelengths) -> SpectralSample {
+    let mut s = [0.0; HERO_COUNT];
+    for (value, lambda) in s.iter_mut().zip(lambdas.iter()) {
+        *value = rgb_to_spectrum(rgb, *lambda);
+    }
+    s
+}
+
+pub fn spectral_sample_to_rgb(radiance: &SpectralSample, lambdas: &Wavelengths) -> Color3 {
+    let mut xyz = Vec3::new();
+    for (value, lambda) in radiance.iter().zip(lambdas.iter()) {
+        xyz += *value * cie_xyz(*lambda);
+    }
+    xyz *= (LAMBDA_MAX - LAMBDA_MIN) / (HERO_COUNT as f64 * CIE_Y_INTEGRAL);
+    let rgb = xyz_to_linear_srgb(&xyz);
+    Color3::construct(&[
+        rgb.x() / EQUAL_ENERGY_RGB[0],
+        rgb.y() / EQUAL_ENERGY_RGB[1],
+        rgb.z() / EQUAL_ENERGY_RGB[2],
+    ])
+}
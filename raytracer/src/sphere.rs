@@ -29,6 +29,23 @@ impl Sphere {
             mat_ptr,
         }
     }
+    fn uv_derivatives(&self, n: &Vec3) -> (Vec3, Vec3) {
+        // Derivatives of get_sphere_uv's parameterization; dpdv is zero at the poles.
+        let r = self.radius;
+        let dpdu = 2.0 * PI * r * Vec3::construct(&[n.z(), 0.0, -n.x()]);
+        let sin_theta = (1.0 - n.y() * n.y()).max(0.0).sqrt();
+        if sin_theta < 1e-8 {
+            return (dpdu, Vec3::new());
+        }
+        let dpdv = PI
+            * r
+            * Vec3::construct(&[
+                -n.y() * n.x() / sin_theta,
+                sin_theta,
+                -n.y() * n.z() / sin_theta,
+            ]);
+        (dpdu, dpdv)
+    }
     fn get_sphere_uv(p: &Point3, u: &mut f64, v: &mut f64) {
         let theta: f64 = (-p.y()).acos();
         let phi: f64 = (-p.z()).atan2(p.x()) + PI;
@@ -59,6 +76,8 @@ impl Hittable for Sphere {
         rec.p = r.at(rec.t);
         let outward_normal: Vec3 = (rec.p - self.center) / self.radius;
         rec.set_face_normal(r, &outward_normal);
+        let (dpdu, dpdv) = self.uv_derivatives(&outward_normal);
+        rec.set_uv_derivatives(&dpdu, &dpdv);
         Sphere::get_sphere_uv(&outward_normal, &mut rec.u, &mut rec.v);
         rec.mat_ptr = Some(Arc::clone(&self.mat_ptr));
         true
@@ -72,9 +91,9 @@ impl Hittable for Sphere {
         true
     }
 
-    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f64) -> f64 {
         let mut rec = HitRecord::new();
-        if !self.hit(&Ray::construct(o, v, 0.0), 0.001, INFINITY, &mut rec) {
+        if !self.hit(&Ray::construct(o, v, time), 0.001, INFINITY, &mut rec) {
             return 0.0;
         }
         let cos_theta_max =
@@ -82,7 +101,7 @@ impl Hittable for Sphere {
         let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
         return 1.0 / solid_angle;
     }
-    fn random(&self, o: &Vec3) -> Vec3 {
+    fn random(&self, o: &Vec3, _time: f64) -> Vec3 {
         let direction = self.center - *o;
         let distance_sq = direction.length_squared();
         let mut uvw = Onb::build_from_w(&direction);
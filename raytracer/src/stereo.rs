@@ -0,0 +1,133 @@
+use crate::camera::{Camera, EquirectangularCamera, PerspectiveCamera};
+use crate::ray::Ray;
+use crate::rt_weekend::{random_double_range, PI};
+use crate::vec3::dot;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StereoLayout {
+    // Left eye on the left half.
+    SideBySide,
+    // Left eye on the top half.
+    TopBottom,
+}
+impl StereoLayout {
+    // Which eye a film point belongs to, and its coordinates within that eye.
+    fn split(&self, s: f64, t: f64) -> (bool, f64, f64) {
+        match self {
+            StereoLayout::SideBySide => {
+                if s < 0.5 {
+                    (true, 2.0 * s, t)
+                } else {
+                    (false, 2.0 * s - 1.0, t)
+                }
+            }
+            StereoLayout::TopBottom => {
+                if t >= 0.5 {
+                    (true, s, 2.0 * t - 1.0)
+                } else {
+                    (false, s, 2.0 * t)
+                }
+            }
+        }
+    }
+    fn scale_differentials(&self, ds: f64, dt: f64) -> (f64, f64) {
+        match self {
+            StereoLayout::SideBySide => (2.0 * ds, dt),
+            StereoLayout::TopBottom => (ds, 2.0 * dt),
+        }
+    }
+}
+
+// Off-axis stereo rig around a perspective camera. The eyes sit on the
+// camera's u axis and keep its view direction; their windows are sheared so
+// that objects at the convergence distance have zero parallax. The center
+// camera's aspect ratio is per eye, i.e. half the image for SideBySide.
+#[derive(Clone)]
+pub struct StereoCamera {
+    pub left: PerspectiveCamera,
+    pub right: PerspectiveCamera,
+    pub layout: StereoLayout,
+}
+impl StereoCamera {
+    pub fn new(
+        center: &PerspectiveCamera,
+        interocular: f64,
+        convergence: f64,
+        layout: StereoLayout,
+    ) -> Self {
+        let window_center =
+            center.lower_left_corner + center.horizontal / 2.0 + center.vertical / 2.0;
+        let focus_dist = dot(&(center.origin - window_center), &center.w);
+        let eye = |offset: f64| {
+            let mut cam = center.clone();
+            cam.origin += offset * center.u;
+            cam.lower_left_corner += offset * (1.0 - focus_dist / convergence) * center.u;
+            cam
+        };
+        Self {
+            left: eye(-interocular / 2.0),
+            right: eye(interocular / 2.0),
+            layout,
+        }
+    }
+}
+impl Camera for StereoCamera {
+    fn get_ray(&self, s: f64, t: f64) -> Option<Ray> {
+        let (is_left, s, t) = self.layout.split(s, t);
+        if is_left {
+            self.left.get_ray(s, t)
+        } else {
+            self.right.get_ray(s, t)
+        }
+    }
+    fn get_ray_differential(&self, s: f64, t: f64, ds: f64, dt: f64) -> Option<Ray> {
+        let (is_left, s, t) = self.layout.split(s, t);
+        let (ds, dt) = self.layout.scale_differentials(ds, dt);
+        if is_left {
+            self.left.get_ray_differential(s, t, ds, dt)
+        } else {
+            self.right.get_ray_differential(s, t, ds, dt)
+        }
+    }
+}
+
+// Omni-directional stereo: an equirectangular panorama per eye, where each
+// column is seen from an eye on a circle of diameter `interocular`, offset
+// tangentially to that column's viewing direction.
+#[derive(Clone)]
+pub struct OdsCamera {
+    pub panorama: EquirectangularCamera,
+    pub interocular: f64,
+    pub layout: StereoLayout,
+}
+impl OdsCamera {
+    pub fn new(panorama: &EquirectangularCamera, interocular: f64, layout: StereoLayout) -> Self {
+        Self {
+            panorama: panorama.clone(),
+            interocular,
+            layout,
+        }
+    }
+}
+impl Camera for OdsCamera {
+    fn get_ray(&self, s: f64, t: f64) -> Option<Ray> {
+        let (is_left, s, t) = self.layout.split(s, t);
+        let pano = &self.panorama;
+        let longitude = 2.0 * PI * (s - 0.5);
+        let latitude = PI * (t - 0.5);
+        let tangent = longitude.cos() * pano.u + longitude.sin() * pano.w;
+        // Shrink the baseline towards the poles, where the eye circle would
+        // otherwise produce a visible swirl.
+        let half_baseline = 0.5 * self.interocular * latitude.cos();
+        let offset = if is_left {
+            -half_baseline
+        } else {
+            half_baseline
+        };
+        Some(Ray::construct(
+            &(pano.origin + offset * tangent),
+            &pano.direction(s, t),
+            random_double_range(pano.time0, pano.time1),
+        ))
+    }
+}
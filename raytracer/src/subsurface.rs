@@ -0,0 +1,125 @@
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::{Dielectric, HenyeyGreenstein, Material, ScatterRecord};
+use crate::ray::Ray;
+use crate::rt_weekend::{random_double, random_int};
+use crate::vec3::{Color3, Point3, Vec3};
+use std::sync::Arc;
+
+// Random-walk subsurface scattering: light refracts into the boundary, then
+// takes exponential free flights with Henyey-Greenstein bounces until it
+// refracts back out.
+#[derive(Debug)]
+pub struct Subsurface {
+    pub boundary: Arc<dyn Hittable>,
+    pub interface: Arc<Dielectric>,
+    pub sigma_t: Color3,
+    pub albedo: Color3,
+    pub g: f64,
+}
+impl Subsurface {
+    pub fn construct(
+        b: Arc<dyn Hittable>,
+        albedo: &Color3,
+        mean_free_path: &Color3,
+        g: f64,
+        ir: f64,
+    ) -> Self {
+        Self {
+            boundary: Arc::clone(&b),
+            interface: Arc::new(Dielectric::construct(ir)),
+            sigma_t: Color3::construct(&[
+                1.0 / mean_free_path.x(),
+                1.0 / mean_free_path.y(),
+                1.0 / mean_free_path.z(),
+            ]),
+            albedo: *albedo,
+            g,
+        }
+    }
+    fn transmittance(&self, distance: f64) -> Color3 {
+        Color3::construct(&[
+            (-self.sigma_t.x() * distance).exp(),
+            (-self.sigma_t.y() * distance).exp(),
+            (-self.sigma_t.z() * distance).exp(),
+        ])
+    }
+    fn average(c: &Color3) -> f64 {
+        (c.x() + c.y() + c.z()) / 3.0
+    }
+}
+
+#[derive(Debug)]
+struct WeightedInterface {
+    interface: Arc<Dielectric>,
+    weight: Color3,
+}
+impl Material for WeightedInterface {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+        if !self.interface.scatter(r_in, rec, srec) {
+            return false;
+        }
+        srec.attenuation = srec.attenuation * self.weight;
+        true
+    }
+    // Built per hit; reports the boundary's ID.
+    fn id(&self) -> u32 {
+        self.interface.id()
+    }
+}
+
+impl Hittable for Subsurface {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let mut surface = HitRecord::new();
+        if !self.boundary.hit(r, t_min, t_max, &mut surface) {
+            return false;
+        }
+        if surface.front_face {
+            // Arriving from outside: just the refractive interface.
+            *rec = surface;
+            rec.mat_ptr = Some(self.interface.clone());
+            return true;
+        }
+
+        // Inside: sample a free-flight distance with one channel's extinction,
+        // and weight by the average pdf over all three (spectral MIS).
+        let ray_length = r.direction().length();
+        let distance_to_exit = (surface.t - t_min) * ray_length;
+        let channel = random_int(0, 2) as usize;
+        let distance = -(random_double().ln()) / self.sigma_t.e[channel];
+
+        if distance < distance_to_exit {
+            let tr = self.transmittance(distance);
+            let pdf = Self::average(&(self.sigma_t * tr));
+            let weight = self.albedo * self.sigma_t * tr / pdf;
+            rec.t = t_min + distance / ray_length;
+            rec.p = r.at(rec.t);
+            rec.normal = Vec3::construct(&[1.0, 0.0, 0.0]); // arbitrary
+            rec.time = r.time();
+            rec.shading_normal = rec.normal;
+            rec.front_face = true; // also arbitrary
+            rec.u = 0.0;
+            rec.v = 0.0;
+            rec.mat_ptr = Some(Arc::new(HenyeyGreenstein::construct_color(&weight, self.g)));
+            return true;
+        }
+
+        let tr = self.transmittance(distance_to_exit);
+        let weight = tr / Self::average(&tr);
+        *rec = surface;
+        rec.mat_ptr = Some(Arc::new(WeightedInterface {
+            interface: self.interface.clone(),
+            weight,
+        }));
+        true
+    }
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut Aabb) -> bool {
+        self.boundary.bounding_box(time0, time1, output_box)
+    }
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f64) -> f64 {
+        self.boundary.pdf_value(o, v, time)
+    }
+    fn random(&self, o: &Vec3, time: f64) -> Vec3 {
+        self.boundary.random(o, time)
+    }
+}
@@ -1,11 +1,27 @@
-use crate::perlin::Perlin;
-use crate::rt_weekend::clamp;
-use crate::vec3::{Color3, Point3};
+use crate::hittable::HitRecord;
+use crate::perlin::{Fade, Perlin};
+use crate::rt_weekend::{clamp, degrees_to_radians, hash_double, PI};
+use crate::vec3::{dot, Color3, Point3, Vec3};
+use image::codecs::hdr::HdrDecoder;
+use std::collections::HashMap;
 use std::fmt::Debug;
-use std::sync::Arc;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Once};
 
 pub trait Texture: Debug + Send + Sync {
     fn value(&self, u: f64, v: f64, p: &Point3) -> Color3;
+    // Filtered lookup using the hit's ray differentials, when a texture can
+    // make use of them.
+    fn value_at(&self, rec: &HitRecord) -> Color3 {
+        self.value(rec.u, rec.v, &rec.p)
+    }
+}
+
+// Widest world-space extent of the pixel footprint at the hit.
+fn footprint(rec: &HitRecord) -> f64 {
+    rec.dpdx.length().max(rec.dpdy.length())
 }
 
 #[derive(Debug)]
@@ -66,12 +82,35 @@ impl Texture for CheckerTexture {
             self.even.value(u, v, p)
         }
     }
+    fn value_at(&self, rec: &HitRecord) -> Color3 {
+        // Fade to the average color once a cell shrinks below about two pixels.
+        let cell = PI / 10.0;
+        let fade = clamp(2.0 * footprint(rec) / cell - 1.0, 0.0, 1.0);
+        let sharp = self.value(rec.u, rec.v, &rec.p);
+        if fade <= 0.0 {
+            return sharp;
+        }
+        let mean = 0.5 * (self.even.value_at(rec) + self.odd.value_at(rec));
+        (1.0 - fade) * sharp + fade * mean
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoisePattern {
+    // The book's marble: sin(scale * z + 10 * turb(p)).
+    Marble,
+    Turbulence,
+    Perlin,
+    Simplex,
 }
 
 #[derive(Debug)]
 pub struct NoiseTexture {
     pub noise: Perlin,
     pub scale: f64,
+    pub pattern: NoisePattern,
+    // Rate at which the pattern evolves with ray time; 0 keeps it static.
+    pub time_scale: f64,
 }
 impl NoiseTexture {
     // pub fn new() -> Self {
@@ -81,61 +120,1047 @@ impl NoiseTexture {
     //     }
     // }
     pub fn construct(scale: f64) -> Self {
+        Self::construct_noise(Perlin::new(), scale)
+    }
+    pub fn construct_seeded(scale: f64, seed: u64) -> Self {
+        Self::construct_noise(Perlin::construct(seed), scale)
+    }
+    pub fn construct_noise(noise: Perlin, scale: f64) -> Self {
         Self {
-            noise: Perlin::new(),
+            noise,
             scale,
+            pattern: NoisePattern::Marble,
+            time_scale: 0.0,
+        }
+    }
+    pub fn with_pattern(mut self, pattern: NoisePattern) -> Self {
+        self.pattern = pattern;
+        self
+    }
+    pub fn with_octaves(mut self, octaves: u32) -> Self {
+        self.noise.octaves = octaves;
+        self
+    }
+    pub fn with_fade(mut self, fade: Fade) -> Self {
+        self.noise.fade = fade;
+        self
+    }
+    pub fn with_time_scale(mut self, time_scale: f64) -> Self {
+        self.time_scale = time_scale;
+        self
+    }
+
+    fn evaluate(&self, p: &Point3, time: f64) -> f64 {
+        // Animated patterns go through the 4D noise; simplex is 3D only.
+        let t = self.time_scale * time;
+        let animated = self.time_scale != 0.0;
+        match self.pattern {
+            NoisePattern::Marble => {
+                let turb = if animated {
+                    self.noise.turb4(p, t)
+                } else {
+                    self.noise.turb(p)
+                };
+                0.5 * (1.0 + (self.scale * p.z() + 10.0 * turb).sin())
+            }
+            NoisePattern::Turbulence => {
+                let q = self.scale * *p;
+                if animated {
+                    self.noise.turb4(&q, t)
+                } else {
+                    self.noise.turb(&q)
+                }
+            }
+            NoisePattern::Perlin => {
+                let q = self.scale * *p;
+                let n = if animated {
+                    self.noise.noise4(&q, t)
+                } else {
+                    self.noise.noise(&q)
+                };
+                0.5 * (1.0 + n)
+            }
+            NoisePattern::Simplex => 0.5 * (1.0 + self.noise.simplex(&(self.scale * *p))),
         }
     }
 }
 impl Texture for NoiseTexture {
     fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color3 {
-        Color3::construct(&[1.0, 1.0, 1.0])
-            * 0.5
-            * (1.0 + (self.scale * p.z() + 10.0 * self.noise.turb(p)).sin())
+        Color3::construct(&[1.0, 1.0, 1.0]) * self.evaluate(p, 0.0)
     }
+    fn value_at(&self, rec: &HitRecord) -> Color3 {
+        let sharp = Color3::construct(&[1.0, 1.0, 1.0]) * self.evaluate(&rec.p, rec.time);
+        if self.pattern != NoisePattern::Marble {
+            return sharp;
+        }
+        // The stripes average out to 0.5 once their period nears the footprint.
+        let period = 2.0 * PI / self.scale;
+        let fade = clamp(4.0 * footprint(rec) / period - 1.0, 0.0, 1.0);
+        (1.0 - fade) * sharp + fade * Color3::construct(&[0.5, 0.5, 0.5])
+    }
+}
+
+fn lerp_color(a: &Color3, b: &Color3, t: f64) -> Color3 {
+    (1.0 - t) * *a + t * *b
 }
 
+fn smoothstep(edge0: f64, edge1: f64, x: f64) -> f64 {
+    let t = clamp((x - edge0) / (edge1 - edge0), 0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// Checkerboard in texture space, `cells` squares across the unit uv square.
 #[derive(Debug)]
-pub struct ImageTexture {
-    pub data: Vec<u8>,
+pub struct UvCheckerTexture {
+    pub even: Arc<dyn Texture>,
+    pub odd: Arc<dyn Texture>,
+    pub cells: [f64; 2],
+}
+impl UvCheckerTexture {
+    pub fn construct(even: Arc<dyn Texture>, odd: Arc<dyn Texture>, cells: &[f64; 2]) -> Self {
+        Self {
+            even: Arc::clone(&even),
+            odd: Arc::clone(&odd),
+            cells: *cells,
+        }
+    }
+    pub fn construct_color(c1: &Color3, c2: &Color3, cells: &[f64; 2]) -> Self {
+        Self::construct(
+            Arc::new(SolidColor::construct(c1)),
+            Arc::new(SolidColor::construct(c2)),
+            cells,
+        )
+    }
+    fn is_even(&self, u: f64, v: f64) -> bool {
+        let i = (u * self.cells[0]).floor() as i64;
+        let j = (v * self.cells[1]).floor() as i64;
+        (i + j).rem_euclid(2) == 0
+    }
+}
+impl Texture for UvCheckerTexture {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color3 {
+        if self.is_even(u, v) {
+            self.even.value(u, v, p)
+        } else {
+            self.odd.value(u, v, p)
+        }
+    }
+    fn value_at(&self, rec: &HitRecord) -> Color3 {
+        if self.is_even(rec.u, rec.v) {
+            self.even.value_at(rec)
+        } else {
+            self.odd.value_at(rec)
+        }
+    }
+}
+
+// Fractional Brownian motion: `octaves` layers of Perlin noise, each
+// `lacunarity` times the frequency and `gain` times the amplitude of the last.
+#[derive(Debug)]
+pub struct FbmTexture {
+    pub noise: Perlin,
+    pub scale: f64,
+    pub octaves: u32,
+    pub lacunarity: f64,
+    pub gain: f64,
+}
+impl FbmTexture {
+    pub fn construct(scale: f64, octaves: u32, lacunarity: f64, gain: f64) -> Self {
+        Self::construct_noise(Perlin::new(), scale, octaves, lacunarity, gain)
+    }
+    pub fn construct_seeded(
+        scale: f64,
+        octaves: u32,
+        lacunarity: f64,
+        gain: f64,
+        seed: u64,
+    ) -> Self {
+        Self::construct_noise(Perlin::construct(seed), scale, octaves, lacunarity, gain)
+    }
+    pub fn construct_noise(
+        noise: Perlin,
+        scale: f64,
+        octaves: u32,
+        lacunarity: f64,
+        gain: f64,
+    ) -> Self {
+        Self {
+            noise,
+            scale,
+            octaves,
+            lacunarity,
+            gain,
+        }
+    }
+    pub fn fbm(&self, p: &Point3) -> f64 {
+        // Normalized by the total amplitude, so the result stays in [-1, 1].
+        let mut accum = 0.0;
+        let mut norm = 0.0;
+        let mut amplitude = 1.0;
+        let mut q = self.scale * *p;
+        for _i in 0..self.octaves {
+            accum += amplitude * self.noise.noise(&q);
+            norm += amplitude;
+            amplitude *= self.gain;
+            q *= self.lacunarity;
+        }
+        if norm > 0.0 {
+            accum / norm
+        } else {
+            0.0
+        }
+    }
+}
+impl Texture for FbmTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color3 {
+        let g = clamp(0.5 + 0.5 * self.fbm(p), 0.0, 1.0);
+        Color3::construct(&[g, g, g])
+    }
+}
+
+// Sine bands along `axis`, distorted by turbulence.
+#[derive(Debug)]
+pub struct MarbleTexture {
+    pub noise: Perlin,
+    pub base: Arc<dyn Texture>,
+    pub vein: Arc<dyn Texture>,
+    pub axis: Vec3,
+    pub frequency: f64,
+    pub turbulence: f64,
+}
+impl MarbleTexture {
+    pub fn construct(
+        base: Arc<dyn Texture>,
+        vein: Arc<dyn Texture>,
+        axis: &Vec3,
+        frequency: f64,
+        turbulence: f64,
+    ) -> Self {
+        Self::construct_noise(Perlin::new(), base, vein, axis, frequency, turbulence)
+    }
+    pub fn construct_seeded(
+        base: Arc<dyn Texture>,
+        vein: Arc<dyn Texture>,
+        axis: &Vec3,
+        frequency: f64,
+        turbulence: f64,
+        seed: u64,
+    ) -> Self {
+        Self::construct_noise(
+            Perlin::construct(seed),
+            base,
+            vein,
+            axis,
+            frequency,
+            turbulence,
+        )
+    }
+    pub fn construct_noise(
+        noise: Perlin,
+        base: Arc<dyn Texture>,
+        vein: Arc<dyn Texture>,
+        axis: &Vec3,
+        frequency: f64,
+        turbulence: f64,
+    ) -> Self {
+        Self {
+            noise,
+            base: Arc::clone(&base),
+            vein: Arc::clone(&vein),
+            axis: axis.unit(),
+            frequency,
+            turbulence,
+        }
+    }
+    pub fn construct_color(
+        base: &Color3,
+        vein: &Color3,
+        axis: &Vec3,
+        frequency: f64,
+        turbulence: f64,
+    ) -> Self {
+        Self::construct(
+            Arc::new(SolidColor::construct(base)),
+            Arc::new(SolidColor::construct(vein)),
+            axis,
+            frequency,
+            turbulence,
+        )
+    }
+    // 0 in the middle of a vein, 1 in the base.
+    fn blend(&self, p: &Point3) -> f64 {
+        let phase = self.frequency * dot(p, &self.axis) + self.turbulence * self.noise.turb(p);
+        0.5 * (1.0 + phase.sin())
+    }
+}
+impl Texture for MarbleTexture {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color3 {
+        let t = self.blend(p);
+        lerp_color(&self.vein.value(u, v, p), &self.base.value(u, v, p), t)
+    }
+    fn value_at(&self, rec: &HitRecord) -> Color3 {
+        let t = self.blend(&rec.p);
+        lerp_color(&self.vein.value_at(rec), &self.base.value_at(rec), t)
+    }
+}
+
+// Concentric rings around the y axis through `center`, with noisy grain.
+#[derive(Debug)]
+pub struct WoodTexture {
+    pub noise: Perlin,
+    pub light: Arc<dyn Texture>,
+    pub dark: Arc<dyn Texture>,
+    pub center: Point3,
+    pub ring_frequency: f64,
+    pub grain: f64,
+    pub grain_scale: f64,
+}
+impl WoodTexture {
+    pub fn construct(
+        light: Arc<dyn Texture>,
+        dark: Arc<dyn Texture>,
+        center: &Point3,
+        ring_frequency: f64,
+        grain: f64,
+        grain_scale: f64,
+    ) -> Self {
+        Self::construct_noise(
+            Perlin::new(),
+            light,
+            dark,
+            center,
+            ring_frequency,
+            grain,
+            grain_scale,
+        )
+    }
+    pub fn construct_seeded(
+        light: Arc<dyn Texture>,
+        dark: Arc<dyn Texture>,
+        center: &Point3,
+        ring_frequency: f64,
+        grain: f64,
+        grain_scale: f64,
+        seed: u64,
+    ) -> Self {
+        Self::construct_noise(
+            Perlin::construct(seed),
+            light,
+            dark,
+            center,
+            ring_frequency,
+            grain,
+            grain_scale,
+        )
+    }
+    pub fn construct_noise(
+        noise: Perlin,
+        light: Arc<dyn Texture>,
+        dark: Arc<dyn Texture>,
+        center: &Point3,
+        ring_frequency: f64,
+        grain: f64,
+        grain_scale: f64,
+    ) -> Self {
+        Self {
+            noise,
+            light: Arc::clone(&light),
+            dark: Arc::clone(&dark),
+            center: *center,
+            ring_frequency,
+            grain,
+            grain_scale,
+        }
+    }
+    pub fn construct_color(
+        light: &Color3,
+        dark: &Color3,
+        center: &Point3,
+        ring_frequency: f64,
+    ) -> Self {
+        Self::construct(
+            Arc::new(SolidColor::construct(light)),
+            Arc::new(SolidColor::construct(dark)),
+            center,
+            ring_frequency,
+            0.3,
+            4.0,
+        )
+    }
+    // 0 in light earlywood, 1 in dark latewood.
+    fn blend(&self, p: &Point3) -> f64 {
+        let d = *p - self.center;
+        let radius = (d.x() * d.x() + d.z() * d.z()).sqrt();
+        let distortion = self.grain * self.noise.noise(&(self.grain_scale * *p));
+        let ring = radius * self.ring_frequency + distortion;
+        // Wide light earlywood, narrow dark latewood.
+        smoothstep(0.6, 0.9, ring - ring.floor())
+    }
+}
+impl Texture for WoodTexture {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color3 {
+        let t = self.blend(p);
+        lerp_color(&self.light.value(u, v, p), &self.dark.value(u, v, p), t)
+    }
+    fn value_at(&self, rec: &HitRecord) -> Color3 {
+        let t = self.blend(&rec.p);
+        lerp_color(&self.light.value_at(rec), &self.dark.value_at(rec), t)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WorleyMode {
+    F1,
+    F2,
+    // Highlights cell borders.
+    F2MinusF1,
+}
+
+// Cellular noise: distances to one jittered feature point per unit cell.
+#[derive(Debug)]
+pub struct WorleyTexture {
+    pub scale: f64,
+    pub mode: WorleyMode,
+    pub seed: u64,
+}
+impl WorleyTexture {
+    pub fn construct(scale: f64, mode: WorleyMode, seed: u64) -> Self {
+        Self { scale, mode, seed }
+    }
+    fn feature_point(&self, cell: &[f64; 3]) -> Point3 {
+        // hash_double mixes raw bits, so every u64 seed stays distinct.
+        let seed = f64::from_bits(self.seed);
+        Point3::construct(&[
+            cell[0] + hash_double(&[seed, cell[0], cell[1], cell[2], 0.0]),
+            cell[1] + hash_double(&[seed, cell[0], cell[1], cell[2], 1.0]),
+            cell[2] + hash_double(&[seed, cell[0], cell[1], cell[2], 2.0]),
+        ])
+    }
+    pub fn distances(&self, p: &Point3) -> (f64, f64) {
+        let q = self.scale * *p;
+        let base = [q.x().floor(), q.y().floor(), q.z().floor()];
+        let mut f1 = f64::INFINITY;
+        let mut f2 = f64::INFINITY;
+        for di in -1..=1 {
+            for dj in -1..=1 {
+                for dk in -1..=1 {
+                    let cell = [
+                        base[0] + di as f64,
+                        base[1] + dj as f64,
+                        base[2] + dk as f64,
+                    ];
+                    let d = (self.feature_point(&cell) - q).length();
+                    if d < f1 {
+                        f2 = f1;
+                        f1 = d;
+                    } else if d < f2 {
+                        f2 = d;
+                    }
+                }
+            }
+        }
+        (f1, f2)
+    }
+}
+impl Texture for WorleyTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color3 {
+        let (f1, f2) = self.distances(p);
+        let g = clamp(
+            match self.mode {
+                WorleyMode::F1 => f1,
+                WorleyMode::F2 => f2,
+                WorleyMode::F2MinusF1 => f2 - f1,
+            },
+            0.0,
+            1.0,
+        );
+        Color3::construct(&[g, g, g])
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum GradientAxis {
+    U,
+    V,
+    // Projection of p onto a world-space direction.
+    World(Vec3),
+}
+
+// Grayscale ramp from 0 at `start` to 1 at `end` along the chosen axis.
+#[derive(Debug)]
+pub struct GradientTexture {
+    pub axis: GradientAxis,
+    pub start: f64,
+    pub end: f64,
+}
+impl GradientTexture {
+    pub fn construct(axis: GradientAxis, start: f64, end: f64) -> Self {
+        Self { axis, start, end }
+    }
+}
+impl Texture for GradientTexture {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color3 {
+        let x = match self.axis {
+            GradientAxis::U => u,
+            GradientAxis::V => v,
+            GradientAxis::World(direction) => dot(p, &direction),
+        };
+        let g = clamp((x - self.start) / (self.end - self.start), 0.0, 1.0);
+        Color3::construct(&[g, g, g])
+    }
+}
+
+// Maps the luminance of `input` through piecewise-linear color stops.
+#[derive(Debug)]
+pub struct ColorRampTexture {
+    pub input: Arc<dyn Texture>,
+    pub stops: Vec<(f64, Color3)>,
+}
+impl ColorRampTexture {
+    pub fn construct(input: Arc<dyn Texture>, stops: &[(f64, Color3)]) -> Self {
+        // A NaN position has no place on the ramp; drop it.
+        let mut stops: Vec<(f64, Color3)> = stops
+            .iter()
+            .copied()
+            .filter(|stop| !stop.0.is_nan())
+            .collect();
+        assert!(!stops.is_empty(), "Color ramp needs at least one stop.");
+        stops.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        Self {
+            input: Arc::clone(&input),
+            stops,
+        }
+    }
+    pub fn evaluate(&self, x: f64) -> Color3 {
+        let first = self.stops[0];
+        let last = self.stops[self.stops.len() - 1];
+        if x <= first.0 {
+            return first.1;
+        }
+        if x >= last.0 {
+            return last.1;
+        }
+        for pair in self.stops.windows(2) {
+            let (x0, c0) = pair[0];
+            let (x1, c1) = pair[1];
+            if x <= x1 {
+                let t = if x1 > x0 { (x - x0) / (x1 - x0) } else { 1.0 };
+                return lerp_color(&c0, &c1, t);
+            }
+        }
+        last.1
+    }
+}
+impl Texture for ColorRampTexture {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color3 {
+        self.evaluate(self.input.value(u, v, p).luminance())
+    }
+    fn value_at(&self, rec: &HitRecord) -> Color3 {
+        self.evaluate(self.input.value_at(rec).luminance())
+    }
+}
+
+// Running bond brick pattern in uv space; sizes are in uv units.
+#[derive(Debug)]
+pub struct BrickTexture {
+    pub brick: Arc<dyn Texture>,
+    pub mortar: Arc<dyn Texture>,
+    pub brick_size: [f64; 2],
+    pub mortar_width: f64,
+    pub row_offset: f64,
+}
+impl BrickTexture {
+    pub fn construct(
+        brick: Arc<dyn Texture>,
+        mortar: Arc<dyn Texture>,
+        brick_size: &[f64; 2],
+        mortar_width: f64,
+    ) -> Self {
+        Self {
+            brick: Arc::clone(&brick),
+            mortar: Arc::clone(&mortar),
+            brick_size: *brick_size,
+            mortar_width,
+            row_offset: 0.5,
+        }
+    }
+    pub fn construct_color(
+        brick: &Color3,
+        mortar: &Color3,
+        brick_size: &[f64; 2],
+        mortar_width: f64,
+    ) -> Self {
+        Self::construct(
+            Arc::new(SolidColor::construct(brick)),
+            Arc::new(SolidColor::construct(mortar)),
+            brick_size,
+            mortar_width,
+        )
+    }
+    fn is_mortar(&self, u: f64, v: f64) -> bool {
+        let y = v / self.brick_size[1];
+        let row = y.floor();
+        let x = u / self.brick_size[0] + self.row_offset * row.rem_euclid(2.0);
+        // Distance to the nearest joint, in uv units.
+        let fx = x - x.floor();
+        let fy = y - row;
+        let dx = fx.min(1.0 - fx) * self.brick_size[0];
+        let dy = fy.min(1.0 - fy) * self.brick_size[1];
+        dx.min(dy) < 0.5 * self.mortar_width
+    }
+}
+impl Texture for BrickTexture {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color3 {
+        if self.is_mortar(u, v) {
+            self.mortar.value(u, v, p)
+        } else {
+            self.brick.value(u, v, p)
+        }
+    }
+    fn value_at(&self, rec: &HitRecord) -> Color3 {
+        if self.is_mortar(rec.u, rec.v) {
+            self.mortar.value_at(rec)
+        } else {
+            self.brick.value_at(rec)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WrapMode {
+    Clamp,
+    Repeat,
+    Mirror,
+}
+impl WrapMode {
+    fn apply(&self, i: i64, n: u32) -> u32 {
+        let n = n as i64;
+        let wrapped = match self {
+            WrapMode::Clamp => i.max(0).min(n - 1),
+            WrapMode::Repeat => i.rem_euclid(n),
+            WrapMode::Mirror => {
+                let m = i.rem_euclid(2 * n);
+                if m < n {
+                    m
+                } else {
+                    2 * n - 1 - m
+                }
+            }
+        };
+        wrapped as u32
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterMode {
+    Nearest,
+    Bilinear,
+    // Bilinear on the two MIP levels around the filter width.
+    Trilinear,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+    // 8-bit color images; decoded to linear on load.
+    Srgb,
+    // Data maps (normal, roughness, height) and float images, used as stored.
+    Linear,
+}
+impl ColorSpace {
+    fn decode(&self, byte: u8) -> f32 {
+        self.decode_unit(byte as f32 / 255.0)
+    }
+    fn decode_unit(&self, c: f32) -> f32 {
+        match self {
+            ColorSpace::Linear => c,
+            ColorSpace::Srgb => {
+                if c <= 0.04045 {
+                    c / 12.92
+                } else {
+                    ((c + 0.055) / 1.055).powf(2.4)
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct MipLevel {
+    // Linear RGB, row-major from the top row.
+    pub data: Vec<f32>,
     pub width: u32,
     pub height: u32,
-    pub bytes_per_scanline: u32,
 }
-impl ImageTexture {
-    pub const BYTES_PER_PIXEL: u32 = 3;
-
-    pub fn construct(data: &[u8], width: u32, height: u32) -> Self {
+impl MipLevel {
+    fn texel(&self, i: u32, j: u32) -> Color3 {
+        let pixel_index: usize = ((j * self.width + i) * ImageTexture::CHANNELS) as usize;
+        Color3::construct(&[
+            self.data[pixel_index] as f64,
+            self.data[pixel_index + 1] as f64,
+            self.data[pixel_index + 2] as f64,
+        ])
+    }
+    fn downsample(&self) -> Self {
+        // 2x2 box filter; odd edges reuse their last row/column.
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let channels = ImageTexture::CHANNELS;
+        let mut data = Vec::with_capacity((width * height * channels) as usize);
+        for j in 0..height {
+            for i in 0..width {
+                for c in 0..channels {
+                    let mut sum = 0.0;
+                    for (di, dj) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                        let x = (2 * i + di).min(self.width - 1);
+                        let y = (2 * j + dj).min(self.height - 1);
+                        sum += self.data[((y * self.width + x) * channels + c) as usize];
+                    }
+                    data.push(0.25 * sum);
+                }
+            }
+        }
         Self {
-            data: data.to_owned(),
+            data,
             width,
             height,
-            bytes_per_scanline: width * Self::BYTES_PER_PIXEL,
         }
     }
 }
-impl Texture for ImageTexture {
-    fn value(&self, mut u: f64, mut v: f64, _p: &Point3) -> Color3 {
-        if self.data.is_empty() {
+
+fn build_mip_chain(data: Vec<f32>, width: u32, height: u32) -> Vec<MipLevel> {
+    let mut levels = vec![MipLevel {
+        data,
+        width,
+        height,
+    }];
+    if !levels[0].data.is_empty() {
+        while levels.last().unwrap().width > 1 || levels.last().unwrap().height > 1 {
+            let next = levels.last().unwrap().downsample();
+            levels.push(next);
+        }
+    }
+    levels
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn load_pfm(path: &Path) -> io::Result<(Vec<f32>, u32, u32)> {
+    // "PF" (RGB) or "Pf" (gray), then width, height and a scale whose sign
+    // gives the byte order. Rows are stored bottom to top.
+    let bytes = fs::read(path)?;
+    let mut header = Vec::new();
+    let mut pos = 0;
+    while header.len() < 4 {
+        while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        let start = pos;
+        while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if start == pos {
+            return Err(invalid_data("Truncated PFM header.".to_owned()));
+        }
+        header.push(String::from_utf8_lossy(&bytes[start..pos]).into_owned());
+    }
+    pos += 1; // single whitespace byte before the raster
+    let channels = match header[0].as_str() {
+        "PF" => 3,
+        "Pf" => 1,
+        other => return Err(invalid_data(format!("Not a PFM file: {}", other))),
+    };
+    let parse = |s: &str| {
+        s.parse::<u32>()
+            .map_err(|_| invalid_data(format!("Invalid PFM dimension: {}", s)))
+    };
+    let width = parse(&header[1])?;
+    let height = parse(&header[2])?;
+    let scale: f32 = header[3]
+        .parse()
+        .map_err(|_| invalid_data(format!("Invalid PFM scale: {}", header[3])))?;
+    let count = (width * height * channels) as usize;
+    if bytes.len() < pos + 4 * count {
+        return Err(invalid_data("Truncated PFM raster.".to_owned()));
+    }
+    let raster: Vec<f32> = bytes[pos..pos + 4 * count]
+        .chunks_exact(4)
+        .map(|b| {
+            let b = [b[0], b[1], b[2], b[3]];
+            if scale < 0.0 {
+                f32::from_le_bytes(b)
+            } else {
+                f32::from_be_bytes(b)
+            }
+        })
+        .collect();
+    let mut data = Vec::with_capacity((width * height * ImageTexture::CHANNELS) as usize);
+    for j in (0..height).rev() {
+        for i in 0..width {
+            let index = ((j * width + i) * channels) as usize;
+            if channels == 3 {
+                data.extend_from_slice(&raster[index..index + 3]);
+            } else {
+                data.extend_from_slice(&[raster[index]; 3]);
+            }
+        }
+    }
+    Ok((data, width, height))
+}
+
+fn load_image(path: &Path, space: ColorSpace) -> io::Result<(Vec<f32>, u32, u32)> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("pfm") => load_pfm(path),
+        Some("hdr") => {
+            let file = BufReader::new(File::open(path)?);
+            let decoder = HdrDecoder::new(file).map_err(|e| invalid_data(e.to_string()))?;
+            let meta = decoder.metadata();
+            let pixels = decoder
+                .read_image_hdr()
+                .map_err(|e| invalid_data(e.to_string()))?;
+            let data = pixels.iter().flat_map(|p| p.0.iter().cloned()).collect();
+            Ok((data, meta.width, meta.height))
+        }
+        _ => {
+            let img = image::open(path).map_err(|e| invalid_data(e.to_string()))?;
+            let color = img.color();
+            if color.bytes_per_pixel() > color.channel_count() {
+                // 16-bit sources keep their full precision.
+                let img = img.into_rgb16();
+                let (width, height) = img.dimensions();
+                let data = img
+                    .into_raw()
+                    .iter()
+                    .map(|w| space.decode_unit(*w as f32 / 65535.0))
+                    .collect();
+                Ok((data, width, height))
+            } else {
+                let img = img.into_rgb8();
+                let (width, height) = img.dimensions();
+                let data = img.into_raw().iter().map(|b| space.decode(*b)).collect();
+                Ok((data, width, height))
+            }
+        }
+    }
+}
+
+type CacheKey = (PathBuf, ColorSpace);
+
+// Decoded MIP chains keyed by file and color space, so scenes that reuse a
+// file only pay for decoding it once.
+#[derive(Debug, Default)]
+pub struct TextureCache {
+    entries: Mutex<HashMap<CacheKey, Arc<Vec<MipLevel>>>>,
+}
+impl TextureCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    // Process-wide cache used by ImageTexture::from_path.
+    pub fn global() -> &'static Self {
+        static INIT: Once = Once::new();
+        static mut GLOBAL: *const TextureCache = std::ptr::null();
+        // Written once under INIT and never freed, so the reference is valid
+        // for the rest of the program.
+        unsafe {
+            INIT.call_once(|| GLOBAL = Box::into_raw(Box::new(TextureCache::new())));
+            &*GLOBAL
+        }
+    }
+    pub fn load<P: AsRef<Path>>(&self, path: P, space: ColorSpace) -> io::Result<ImageTexture> {
+        // Canonical, so different spellings of one file share an entry.
+        let key = (fs::canonicalize(path)?, space);
+        if let Some(levels) = self.entries.lock().unwrap().get(&key) {
+            return Ok(ImageTexture::from_levels(levels.clone()));
+        }
+        // Decode without holding the lock. If another thread got there
+        // first, use its copy so every texture shares one chain.
+        let (data, width, height) = load_image(&key.0, space)?;
+        let decoded = Arc::new(build_mip_chain(data, width, height));
+        let levels = self
+            .entries
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_insert(decoded)
+            .clone();
+        Ok(ImageTexture::from_levels(levels))
+    }
+}
+
+#[derive(Debug)]
+pub struct ImageTexture {
+    // levels[0] is the full-resolution image; shared with the TextureCache.
+    pub levels: Arc<Vec<MipLevel>>,
+    pub wrap: WrapMode,
+    pub filter: FilterMode,
+}
+impl ImageTexture {
+    pub const CHANNELS: u32 = 3;
+
+    pub fn construct(data: &[u8], width: u32, height: u32) -> Self {
+        Self::construct_bytes(data, width, height, ColorSpace::Srgb)
+    }
+    pub fn construct_bytes(data: &[u8], width: u32, height: u32, space: ColorSpace) -> Self {
+        let data = data.iter().map(|b| space.decode(*b)).collect();
+        Self::construct_float(data, width, height)
+    }
+    pub fn construct_float(data: Vec<f32>, width: u32, height: u32) -> Self {
+        Self::from_levels(Arc::new(build_mip_chain(data, width, height)))
+    }
+    pub fn from_path<P: AsRef<Path>>(path: P, space: ColorSpace) -> io::Result<Self> {
+        TextureCache::global().load(path, space)
+    }
+    fn from_levels(levels: Arc<Vec<MipLevel>>) -> Self {
+        Self {
+            levels,
+            wrap: WrapMode::Clamp,
+            filter: FilterMode::Nearest,
+        }
+    }
+    pub fn with_wrap(mut self, wrap: WrapMode) -> Self {
+        self.wrap = wrap;
+        self
+    }
+    pub fn with_filter(mut self, filter: FilterMode) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    fn nearest(&self, level: &MipLevel, u: f64, v: f64) -> Color3 {
+        let i = self
+            .wrap
+            .apply((u * level.width as f64).floor() as i64, level.width);
+        let j = self
+            .wrap
+            .apply((v * level.height as f64).floor() as i64, level.height);
+        level.texel(i, j)
+    }
+    fn bilinear(&self, level: &MipLevel, u: f64, v: f64) -> Color3 {
+        let x = u * level.width as f64 - 0.5;
+        let y = v * level.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let i0 = self.wrap.apply(x0 as i64, level.width);
+        let i1 = self.wrap.apply(x0 as i64 + 1, level.width);
+        let j0 = self.wrap.apply(y0 as i64, level.height);
+        let j1 = self.wrap.apply(y0 as i64 + 1, level.height);
+        (1.0 - fx) * (1.0 - fy) * level.texel(i0, j0)
+            + fx * (1.0 - fy) * level.texel(i1, j0)
+            + (1.0 - fx) * fy * level.texel(i0, j1)
+            + fx * fy * level.texel(i1, j1)
+    }
+    pub fn sample(&self, u: f64, v: f64, width: f64) -> Color3 {
+        // `width` is the filter footprint in uv units, used by Trilinear.
+        if self.levels[0].data.is_empty() {
             return Color3::construct(&[0.0, 1.0, 1.0]);
         }
+        let (u, v) = if self.wrap == WrapMode::Clamp {
+            (clamp(u, 0.0, 1.0), clamp(v, 0.0, 1.0))
+        } else {
+            (u, v)
+        };
+        // Image rows run top to bottom.
+        let v = 1.0 - v;
+        match self.filter {
+            FilterMode::Nearest => self.nearest(&self.levels[0], u, v),
+            FilterMode::Bilinear => self.bilinear(&self.levels[0], u, v),
+            FilterMode::Trilinear => {
+                let base = &self.levels[0];
+                let texels = width * base.width.max(base.height) as f64;
+                let max_level = (self.levels.len() - 1) as f64;
+                let lod = if texels > 1.0 {
+                    clamp(texels.log2(), 0.0, max_level)
+                } else {
+                    0.0
+                };
+                let lo = lod.floor() as usize;
+                let hi = (lo + 1).min(self.levels.len() - 1);
+                let t = lod - lo as f64;
+                (1.0 - t) * self.bilinear(&self.levels[lo], u, v)
+                    + t * self.bilinear(&self.levels[hi], u, v)
+            }
+        }
+    }
+}
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _p: &Point3) -> Color3 {
+        self.sample(u, v, 0.0)
+    }
+    fn value_at(&self, rec: &HitRecord) -> Color3 {
+        let width_x = (rec.dudx * rec.dudx + rec.dvdx * rec.dvdx).sqrt();
+        let width_y = (rec.dudy * rec.dudy + rec.dvdy * rec.dvdy).sqrt();
+        self.sample(rec.u, rec.v, width_x.max(width_y))
+    }
+}
 
-        u = clamp(u, 0.0, 1.0);
-        v = 1.0 - clamp(v, 0.0, 1.0);
-        let mut i: u32 = (u * self.width as f64) as u32;
-        let mut j: u32 = (v * self.height as f64) as u32;
-        if i >= self.width {
-            i = self.width - 1;
+#[derive(Debug, Clone, Copy)]
+pub struct UvTransform {
+    pub scale: [f64; 2],
+    pub offset: [f64; 2],
+    pub rotation: f64, // degrees, counter-clockwise about the uv origin
+}
+impl UvTransform {
+    pub fn identity() -> Self {
+        Self::construct(&[1.0, 1.0], &[0.0, 0.0], 0.0)
+    }
+    pub fn construct(scale: &[f64; 2], offset: &[f64; 2], rotation: f64) -> Self {
+        Self {
+            scale: *scale,
+            offset: *offset,
+            rotation,
         }
-        if j >= self.height {
-            j = self.height - 1;
+    }
+    pub fn apply_vector(&self, du: f64, dv: f64) -> (f64, f64) {
+        // Derivatives transform without the offset.
+        let (u, v) = self.apply(du, dv);
+        (u - self.offset[0], v - self.offset[1])
+    }
+    pub fn apply(&self, u: f64, v: f64) -> (f64, f64) {
+        let su = u * self.scale[0];
+        let sv = v * self.scale[1];
+        let (sin, cos) = degrees_to_radians(self.rotation).sin_cos();
+        (
+            cos * su - sin * sv + self.offset[0],
+            sin * su + cos * sv + self.offset[1],
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct UvTransformTexture {
+    pub texture: Arc<dyn Texture>,
+    pub transform: UvTransform,
+}
+impl UvTransformTexture {
+    pub fn construct(texture: Arc<dyn Texture>, transform: &UvTransform) -> Self {
+        Self {
+            texture: Arc::clone(&texture),
+            transform: *transform,
         }
-        let color_scale: f64 = 1.0 / 255.0;
-        let pixel_index: usize = (j * self.bytes_per_scanline + i * Self::BYTES_PER_PIXEL) as usize;
-        Color3::construct(&[
-            color_scale * self.data[pixel_index] as f64,
-            color_scale * self.data[pixel_index + 1] as f64,
-            color_scale * self.data[pixel_index + 2] as f64,
-        ])
+    }
+}
+impl Texture for UvTransformTexture {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color3 {
+        let (u, v) = self.transform.apply(u, v);
+        self.texture.value(u, v, p)
+    }
+    fn value_at(&self, rec: &HitRecord) -> Color3 {
+        let mut local = rec.clone();
+        let (u, v) = self.transform.apply(rec.u, rec.v);
+        let (dudx, dvdx) = self.transform.apply_vector(rec.dudx, rec.dvdx);
+        let (dudy, dvdy) = self.transform.apply_vector(rec.dudy, rec.dvdy);
+        local.u = u;
+        local.v = v;
+        local.dudx = dudx;
+        local.dvdx = dvdx;
+        local.dudy = dudy;
+        local.dvdy = dvdy;
+        self.texture.value_at(&local)
     }
 }
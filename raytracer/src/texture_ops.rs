@@ -0,0 +1,302 @@
+use crate::hittable::HitRecord;
+use crate::rt_weekend::clamp;
+use crate::texture::Texture;
+use crate::vec3::{Color3, Point3, Vec3};
+use std::sync::Arc;
+
+// Combinators over Arc<dyn Texture>, for building shading networks such as
+// noise * color + image. Each forwards value_at so filtering still applies
+// to the leaves.
+
+#[derive(Debug)]
+pub struct AddTexture {
+    pub a: Arc<dyn Texture>,
+    pub b: Arc<dyn Texture>,
+}
+impl AddTexture {
+    pub fn construct(a: Arc<dyn Texture>, b: Arc<dyn Texture>) -> Self {
+        Self {
+            a: Arc::clone(&a),
+            b: Arc::clone(&b),
+        }
+    }
+}
+impl Texture for AddTexture {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color3 {
+        self.a.value(u, v, p) + self.b.value(u, v, p)
+    }
+    fn value_at(&self, rec: &HitRecord) -> Color3 {
+        self.a.value_at(rec) + self.b.value_at(rec)
+    }
+}
+
+#[derive(Debug)]
+pub struct MultiplyTexture {
+    pub a: Arc<dyn Texture>,
+    pub b: Arc<dyn Texture>,
+}
+impl MultiplyTexture {
+    pub fn construct(a: Arc<dyn Texture>, b: Arc<dyn Texture>) -> Self {
+        Self {
+            a: Arc::clone(&a),
+            b: Arc::clone(&b),
+        }
+    }
+}
+impl Texture for MultiplyTexture {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color3 {
+        self.a.value(u, v, p) * self.b.value(u, v, p)
+    }
+    fn value_at(&self, rec: &HitRecord) -> Color3 {
+        self.a.value_at(rec) * self.b.value_at(rec)
+    }
+}
+
+// a where the mask's luminance is 0, b where it is 1.
+#[derive(Debug)]
+pub struct LerpTexture {
+    pub a: Arc<dyn Texture>,
+    pub b: Arc<dyn Texture>,
+    pub mask: Arc<dyn Texture>,
+}
+impl LerpTexture {
+    pub fn construct(a: Arc<dyn Texture>, b: Arc<dyn Texture>, mask: Arc<dyn Texture>) -> Self {
+        Self {
+            a: Arc::clone(&a),
+            b: Arc::clone(&b),
+            mask: Arc::clone(&mask),
+        }
+    }
+    fn mix(a: Color3, b: Color3, t: f64) -> Color3 {
+        (1.0 - t) * a + t * b
+    }
+}
+impl Texture for LerpTexture {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color3 {
+        let t = self.mask.value(u, v, p).luminance();
+        Self::mix(self.a.value(u, v, p), self.b.value(u, v, p), t)
+    }
+    fn value_at(&self, rec: &HitRecord) -> Color3 {
+        let t = self.mask.value_at(rec).luminance();
+        Self::mix(self.a.value_at(rec), self.b.value_at(rec), t)
+    }
+}
+
+// Linearly maps each channel from [from[0], from[1]] to [to[0], to[1]].
+#[derive(Debug)]
+pub struct RemapTexture {
+    pub input: Arc<dyn Texture>,
+    pub from: [f64; 2],
+    pub to: [f64; 2],
+    pub clamp: bool,
+}
+impl RemapTexture {
+    pub fn construct(input: Arc<dyn Texture>, from: &[f64; 2], to: &[f64; 2], clamp: bool) -> Self {
+        Self {
+            input: Arc::clone(&input),
+            from: *from,
+            to: *to,
+            clamp,
+        }
+    }
+    fn remap(&self, c: Color3) -> Color3 {
+        let mut out = Color3::new();
+        for i in 0..3 {
+            let mut t = (c.e[i] - self.from[0]) / (self.from[1] - self.from[0]);
+            if self.clamp {
+                t = clamp(t, 0.0, 1.0);
+            }
+            out.e[i] = self.to[0] + t * (self.to[1] - self.to[0]);
+        }
+        out
+    }
+}
+impl Texture for RemapTexture {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color3 {
+        self.remap(self.input.value(u, v, p))
+    }
+    fn value_at(&self, rec: &HitRecord) -> Color3 {
+        self.remap(self.input.value_at(rec))
+    }
+}
+
+#[derive(Debug)]
+pub struct InvertTexture {
+    pub input: Arc<dyn Texture>,
+}
+impl InvertTexture {
+    pub fn construct(input: Arc<dyn Texture>) -> Self {
+        Self {
+            input: Arc::clone(&input),
+        }
+    }
+}
+impl Texture for InvertTexture {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color3 {
+        Color3::construct(&[1.0, 1.0, 1.0]) - self.input.value(u, v, p)
+    }
+    fn value_at(&self, rec: &HitRecord) -> Color3 {
+        Color3::construct(&[1.0, 1.0, 1.0]) - self.input.value_at(rec)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Channel {
+    R,
+    G,
+    B,
+    Luminance,
+    Zero,
+    One,
+}
+
+// Rebuilds each output channel from a channel of the input, e.g. to pull a
+// roughness map out of the green channel of a packed image.
+#[derive(Debug)]
+pub struct SwizzleTexture {
+    pub input: Arc<dyn Texture>,
+    pub channels: [Channel; 3],
+}
+impl SwizzleTexture {
+    pub fn construct(input: Arc<dyn Texture>, channels: &[Channel; 3]) -> Self {
+        Self {
+            input: Arc::clone(&input),
+            channels: *channels,
+        }
+    }
+    pub fn construct_single(input: Arc<dyn Texture>, channel: Channel) -> Self {
+        Self::construct(input, &[channel; 3])
+    }
+    fn swizzle(&self, c: Color3) -> Color3 {
+        let mut out = Color3::new();
+        for (o, channel) in out.e.iter_mut().zip(self.channels.iter()) {
+            *o = match channel {
+                Channel::R => c.x(),
+                Channel::G => c.y(),
+                Channel::B => c.z(),
+                Channel::Luminance => c.luminance(),
+                Channel::Zero => 0.0,
+                Channel::One => 1.0,
+            };
+        }
+        out
+    }
+}
+impl Texture for SwizzleTexture {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color3 {
+        self.swizzle(self.input.value(u, v, p))
+    }
+    fn value_at(&self, rec: &HitRecord) -> Color3 {
+        self.swizzle(self.input.value_at(rec))
+    }
+}
+
+// Scales the lookup coordinates (uv and p) before sampling `input`.
+#[derive(Debug)]
+pub struct ScaleCoordsTexture {
+    pub input: Arc<dyn Texture>,
+    pub uv_scale: [f64; 2],
+    pub p_scale: Vec3,
+}
+impl ScaleCoordsTexture {
+    pub fn construct(input: Arc<dyn Texture>, uv_scale: &[f64; 2], p_scale: &Vec3) -> Self {
+        Self {
+            input: Arc::clone(&input),
+            uv_scale: *uv_scale,
+            p_scale: *p_scale,
+        }
+    }
+    pub fn construct_uniform(input: Arc<dyn Texture>, scale: f64) -> Self {
+        Self::construct(
+            input,
+            &[scale, scale],
+            &Vec3::construct(&[scale, scale, scale]),
+        )
+    }
+}
+impl Texture for ScaleCoordsTexture {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color3 {
+        self.input.value(
+            u * self.uv_scale[0],
+            v * self.uv_scale[1],
+            &(*p * self.p_scale),
+        )
+    }
+    fn value_at(&self, rec: &HitRecord) -> Color3 {
+        let mut local = rec.clone();
+        local.u *= self.uv_scale[0];
+        local.v *= self.uv_scale[1];
+        local.p = rec.p * self.p_scale;
+        local.dudx *= self.uv_scale[0];
+        local.dudy *= self.uv_scale[0];
+        local.dvdx *= self.uv_scale[1];
+        local.dvdy *= self.uv_scale[1];
+        local.dpdx = rec.dpdx * self.p_scale;
+        local.dpdy = rec.dpdy * self.p_scale;
+        self.input.value_at(&local)
+    }
+}
+
+// Projects `input` along the three world axes and blends the projections by
+// the surface normal, for objects without a usable uv parameterization.
+#[derive(Debug)]
+pub struct TriplanarTexture {
+    pub input: Arc<dyn Texture>,
+    pub scale: f64,
+    pub sharpness: f64,
+}
+impl TriplanarTexture {
+    // (u axis, v axis) for the projections along x, y and z.
+    const PLANES: [(usize, usize); 3] = [(2, 1), (0, 2), (0, 1)];
+
+    pub fn construct(input: Arc<dyn Texture>, scale: f64, sharpness: f64) -> Self {
+        Self {
+            input: Arc::clone(&input),
+            scale,
+            sharpness,
+        }
+    }
+    fn weights(&self, n: &Vec3) -> [f64; 3] {
+        let mut w = [
+            n.x().abs().powf(self.sharpness),
+            n.y().abs().powf(self.sharpness),
+            n.z().abs().powf(self.sharpness),
+        ];
+        let sum = w[0] + w[1] + w[2];
+        for x in w.iter_mut() {
+            *x /= sum;
+        }
+        w
+    }
+}
+impl Texture for TriplanarTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color3 {
+        // No normal here, so weight the projections equally.
+        let mut accum = Color3::new();
+        for (a, b) in Self::PLANES.iter() {
+            accum += self
+                .input
+                .value(self.scale * p.e[*a], self.scale * p.e[*b], p)
+                / 3.0;
+        }
+        accum
+    }
+    fn value_at(&self, rec: &HitRecord) -> Color3 {
+        let w = self.weights(&rec.shading_normal);
+        let mut accum = Color3::new();
+        for (weight, (a, b)) in w.iter().zip(Self::PLANES.iter()) {
+            if *weight <= 0.0 {
+                continue;
+            }
+            let mut local = rec.clone();
+            local.u = self.scale * rec.p.e[*a];
+            local.v = self.scale * rec.p.e[*b];
+            local.dudx = self.scale * rec.dpdx.e[*a];
+            local.dvdx = self.scale * rec.dpdx.e[*b];
+            local.dudy = self.scale * rec.dpdy.e[*a];
+            local.dvdy = self.scale * rec.dpdy.e[*b];
+            accum += *weight * self.input.value_at(&local);
+        }
+        accum
+    }
+}
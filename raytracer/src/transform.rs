@@ -0,0 +1,471 @@
+use crate::aabb::Aabb;
+use crate::animation::TransformTrack;
+use crate::hittable::{HitRecord, Hittable};
+use crate::ray::Ray;
+use crate::rt_weekend::degrees_to_radians;
+use crate::vec3::{Point3, Vec3};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Matrix4 {
+    pub m: [[f64; 4]; 4],
+}
+impl Matrix4 {
+    pub fn identity() -> Self {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            row[i] = 1.0;
+        }
+        Self { m }
+    }
+    pub fn translate(offset: &Vec3) -> Self {
+        let mut r = Self::identity();
+        for i in 0..3 {
+            r.m[i][3] = offset.e[i];
+        }
+        r
+    }
+    pub fn scale(s: &Vec3) -> Self {
+        let mut r = Self::identity();
+        for i in 0..3 {
+            r.m[i][i] = s.e[i];
+        }
+        r
+    }
+    pub fn rotate(axis: &Vec3, angle: f64) -> Self {
+        // Rodrigues' formula; angle in degrees, counter-clockwise about axis.
+        let a = axis.unit();
+        let (sin, cos) = degrees_to_radians(angle).sin_cos();
+        let mut r = Self::identity();
+        for i in 0..3 {
+            for j in 0..3 {
+                r.m[i][j] = a.e[i] * a.e[j] * (1.0 - cos) + if i == j { cos } else { 0.0 };
+            }
+        }
+        r.m[0][1] -= a.z() * sin;
+        r.m[0][2] += a.y() * sin;
+        r.m[1][0] += a.z() * sin;
+        r.m[1][2] -= a.x() * sin;
+        r.m[2][0] -= a.y() * sin;
+        r.m[2][1] += a.x() * sin;
+        r
+    }
+    pub fn rotate_euler(degrees: &Vec3) -> Self {
+        // X first, then Y, then Z.
+        Self::rotate(&Vec3::construct(&[0.0, 0.0, 1.0]), degrees.z())
+            * Self::rotate(&Vec3::construct(&[0.0, 1.0, 0.0]), degrees.y())
+            * Self::rotate(&Vec3::construct(&[1.0, 0.0, 0.0]), degrees.x())
+    }
+    pub fn transpose(&self) -> Self {
+        let mut r = [[0.0; 4]; 4];
+        for (i, row) in r.iter_mut().enumerate() {
+            for (j, x) in row.iter_mut().enumerate() {
+                *x = self.m[j][i];
+            }
+        }
+        Self { m: r }
+    }
+    pub fn inverse(&self) -> Option<Self> {
+        // Gauss-Jordan elimination with partial pivoting.
+        let mut a = self.m;
+        let mut inv = Self::identity().m;
+        for col in 0..4 {
+            let pivot = (col..4)
+                .max_by(|&i, &j| a[i][col].abs().partial_cmp(&a[j][col].abs()).unwrap())
+                .unwrap();
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+            let d = a[col][col];
+            for j in 0..4 {
+                a[col][j] /= d;
+                inv[col][j] /= d;
+            }
+            for row in 0..4 {
+                if row != col {
+                    let f = a[row][col];
+                    for j in 0..4 {
+                        a[row][j] -= f * a[col][j];
+                        inv[row][j] -= f * inv[col][j];
+                    }
+                }
+            }
+        }
+        Some(Self { m: inv })
+    }
+    pub fn transform_point(&self, p: &Point3) -> Point3 {
+        let m = &self.m;
+        let mut r = Point3::new();
+        for (x, row) in r.e.iter_mut().zip(m.iter()) {
+            *x = row[0] * p.x() + row[1] * p.y() + row[2] * p.z() + row[3];
+        }
+        let w = m[3][0] * p.x() + m[3][1] * p.y() + m[3][2] * p.z() + m[3][3];
+        if w != 1.0 {
+            r /= w;
+        }
+        r
+    }
+    pub fn transform_vector(&self, v: &Vec3) -> Vec3 {
+        let m = &self.m;
+        let mut r = Vec3::new();
+        for (x, row) in r.e.iter_mut().zip(m.iter()) {
+            *x = row[0] * v.x() + row[1] * v.y() + row[2] * v.z();
+        }
+        r
+    }
+}
+impl std::ops::Mul for Matrix4 {
+    type Output = Matrix4;
+    fn mul(self, rhs: Matrix4) -> Matrix4 {
+        let mut r = [[0.0; 4]; 4];
+        for (i, row) in r.iter_mut().enumerate() {
+            for (j, x) in row.iter_mut().enumerate() {
+                *x = (0..4).map(|k| self.m[i][k] * rhs.m[k][j]).sum();
+            }
+        }
+        Matrix4 { m: r }
+    }
+}
+
+// Object-to-world matrix together with its inverse.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub m: Matrix4,
+    pub m_inv: Matrix4,
+}
+impl Transform {
+    pub fn identity() -> Self {
+        Self::construct(&Matrix4::identity())
+    }
+    pub fn construct(m: &Matrix4) -> Self {
+        Self {
+            m: *m,
+            m_inv: m.inverse().expect("Transform matrix is singular."),
+        }
+    }
+    // Translation * rotation (Euler degrees, X then Y then Z) * scale. The
+    // inverse is built from the inverted factors, which is exact and cheap
+    // enough to do for every ray of an animated object.
+    pub fn from_trs(translation: &Vec3, rotation: &Vec3, scale: &Vec3) -> Self {
+        assert!(
+            scale.e.iter().all(|s| *s != 0.0),
+            "Transform matrix is singular."
+        );
+        let rotation = Matrix4::rotate_euler(rotation);
+        let inv_scale = Vec3::construct(&[1.0 / scale.x(), 1.0 / scale.y(), 1.0 / scale.z()]);
+        Self {
+            m: Matrix4::translate(translation) * rotation * Matrix4::scale(scale),
+            m_inv: Matrix4::scale(&inv_scale)
+                * rotation.transpose()
+                * Matrix4::translate(&-*translation),
+        }
+    }
+    pub fn inverse(&self) -> Self {
+        Self {
+            m: self.m_inv,
+            m_inv: self.m,
+        }
+    }
+    pub fn apply_point(&self, p: &Point3) -> Point3 {
+        self.m.transform_point(p)
+    }
+    pub fn apply_vector(&self, v: &Vec3) -> Vec3 {
+        self.m.transform_vector(v)
+    }
+    pub fn apply_normal(&self, n: &Vec3) -> Vec3 {
+        // Normals go through the inverse transpose.
+        self.m_inv.transpose().transform_vector(n)
+    }
+    pub fn apply_ray(&self, r: &Ray) -> Ray {
+        // Direction is left unnormalized so hit distances carry over.
+        Ray::construct(
+            &self.apply_point(&r.origin()),
+            &self.apply_vector(&r.direction()),
+            r.time(),
+        )
+        .with_wavelength(r.wavelength())
+    }
+    pub fn apply_box(&self, b: &Aabb) -> Aabb {
+        let mut min = Point3::construct(&[f64::INFINITY; 3]);
+        let mut max = Point3::construct(&[f64::NEG_INFINITY; 3]);
+        for corner in 0..8 {
+            let p = Point3::construct(&[
+                if corner & 1 == 0 {
+                    b.minimum().x()
+                } else {
+                    b.maximum().x()
+                },
+                if corner & 2 == 0 {
+                    b.minimum().y()
+                } else {
+                    b.maximum().y()
+                },
+                if corner & 4 == 0 {
+                    b.minimum().z()
+                } else {
+                    b.maximum().z()
+                },
+            ]);
+            let q = self.apply_point(&p);
+            for a in 0..3 {
+                min.e[a] = min.e[a].min(q.e[a]);
+                max.e[a] = max.e[a].max(q.e[a]);
+            }
+        }
+        Aabb::construct(&min, &max)
+    }
+    // Brings an object-space hit record into world space; `r` is the world ray.
+    pub fn apply_hit(&self, r: &Ray, rec: &mut HitRecord) {
+        rec.p = self.apply_point(&rec.p);
+        let outward = if rec.front_face {
+            rec.normal
+        } else {
+            -rec.normal
+        };
+        let dpdu = self.apply_vector(&rec.dpdu);
+        let dpdv = self.apply_vector(&rec.dpdv);
+        rec.set_face_normal(r, &self.apply_normal(&outward).unit());
+        rec.set_uv_derivatives(&dpdu, &dpdv);
+    }
+}
+
+// Any hittable placed by a general affine transform. pdf_value and random
+// assume the transform is rigid, since solid angles are otherwise distorted.
+#[derive(Debug)]
+pub struct TransformedHittable {
+    pub ptr: Arc<dyn Hittable>,
+    pub transform: Transform,
+}
+impl TransformedHittable {
+    pub fn construct(p: Arc<dyn Hittable>, transform: &Transform) -> Self {
+        Self {
+            ptr: Arc::clone(&p),
+            transform: *transform,
+        }
+    }
+}
+impl Hittable for TransformedHittable {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let local_r = self.transform.inverse().apply_ray(r);
+        if !self.ptr.hit(&local_r, t_min, t_max, rec) {
+            return false;
+        }
+        self.transform.apply_hit(r, rec);
+        true
+    }
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut Aabb) -> bool {
+        if !self.ptr.bounding_box(time0, time1, output_box) {
+            return false;
+        }
+        *output_box = self.transform.apply_box(output_box);
+        true
+    }
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f64) -> f64 {
+        let inv = self.transform.inverse();
+        self.ptr
+            .pdf_value(&inv.apply_point(o), &inv.apply_vector(v), time)
+    }
+    fn random(&self, o: &Vec3, time: f64) -> Vec3 {
+        let local_o = self.transform.inverse().apply_point(o);
+        self.transform
+            .apply_vector(&self.ptr.random(&local_o, time))
+    }
+}
+
+// Any hittable moving along a keyframed transform, evaluated at each ray's
+// time; gives motion blur to lists, BVHs and meshes alike. As with
+// TransformedHittable, light sampling assumes the motion is rigid.
+#[derive(Debug)]
+pub struct MotionTransform {
+    pub ptr: Arc<dyn Hittable>,
+    pub track: TransformTrack,
+}
+impl MotionTransform {
+    const BOX_SAMPLES: usize = 32;
+
+    pub fn construct(p: Arc<dyn Hittable>, track: TransformTrack) -> Self {
+        Self {
+            ptr: Arc::clone(&p),
+            track,
+        }
+    }
+}
+impl Hittable for MotionTransform {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let transform = self.track.at(r.time());
+        let local_r = transform.inverse().apply_ray(r);
+        if !self.ptr.hit(&local_r, t_min, t_max, rec) {
+            return false;
+        }
+        transform.apply_hit(r, rec);
+        true
+    }
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f64) -> f64 {
+        let inv = self.track.at(time).inverse();
+        self.ptr
+            .pdf_value(&inv.apply_point(o), &inv.apply_vector(v), time)
+    }
+    fn random(&self, o: &Vec3, time: f64) -> Vec3 {
+        let transform = self.track.at(time);
+        let local_o = transform.inverse().apply_point(o);
+        transform.apply_vector(&self.ptr.random(&local_o, time))
+    }
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut Aabb) -> bool {
+        // Union of the child's box over sampled times plus every key inside
+        // [time0, time1]. Rotation can bulge between samples, so the result
+        // is padded by half the largest step any corner makes, which bounds
+        // the arc's deviation from its chord.
+        let mut local = Aabb::default();
+        if !self.ptr.bounding_box(time0, time1, &mut local) {
+            return false;
+        }
+        let mut times: Vec<f64> = (0..=Self::BOX_SAMPLES)
+            .map(|i| time0 + (time1 - time0) * i as f64 / Self::BOX_SAMPLES as f64)
+            .chain(
+                self.track
+                    .key_times()
+                    .into_iter()
+                    .filter(|t| *t > time0 && *t < time1),
+            )
+            .collect();
+        times.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        let corners: Vec<Point3> = (0..8)
+            .map(|c| {
+                Point3::construct(&[
+                    if c & 1 == 0 {
+                        local.minimum().x()
+                    } else {
+                        local.maximum().x()
+                    },
+                    if c & 2 == 0 {
+                        local.minimum().y()
+                    } else {
+                        local.maximum().y()
+                    },
+                    if c & 4 == 0 {
+                        local.minimum().z()
+                    } else {
+                        local.maximum().z()
+                    },
+                ])
+            })
+            .collect();
+        let mut min = Point3::construct(&[f64::INFINITY; 3]);
+        let mut max = Point3::construct(&[f64::NEG_INFINITY; 3]);
+        let mut max_step: f64 = 0.0;
+        let mut previous: Option<Vec<Point3>> = None;
+        for t in times {
+            let transform = self.track.at(t);
+            let moved: Vec<Point3> = corners.iter().map(|c| transform.apply_point(c)).collect();
+            for q in moved.iter() {
+                for a in 0..3 {
+                    min.e[a] = min.e[a].min(q.e[a]);
+                    max.e[a] = max.e[a].max(q.e[a]);
+                }
+            }
+            if let Some(prev) = &previous {
+                for (a, b) in prev.iter().zip(moved.iter()) {
+                    max_step = max_step.max((*b - *a).length());
+                }
+            }
+            previous = Some(moved);
+        }
+        let pad = Vec3::construct(&[0.5 * max_step; 3]);
+        *output_box = Aabb::construct(&(min - pad), &(max + pad));
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animation::{Interpolation, Keyframe, Track};
+    use crate::boxes::Box;
+    use crate::material::Lambertian;
+    use crate::vec3::Color3;
+
+    fn assert_identity(m: &Matrix4) {
+        let id = Matrix4::identity();
+        for i in 0..4 {
+            for j in 0..4 {
+                assert!((m.m[i][j] - id.m[i][j]).abs() < 1e-9, "{:?}", m);
+            }
+        }
+    }
+
+    #[test]
+    fn trs_inverse_undoes_the_transform() {
+        let t = Transform::from_trs(
+            &Vec3::construct(&[3.0, -2.0, 5.0]),
+            &Vec3::construct(&[30.0, -75.0, 120.0]),
+            &Vec3::construct(&[2.0, 0.5, -3.0]),
+        );
+        assert_identity(&(t.m * t.m_inv));
+        assert_identity(&(t.m_inv * t.m));
+        // The general inverse agrees with the analytic one.
+        assert_identity(&(t.m * t.m.inverse().unwrap()));
+    }
+
+    #[test]
+    fn singular_matrix_has_no_inverse() {
+        let m = Matrix4::scale(&Vec3::construct(&[1.0, 0.0, 1.0]));
+        assert!(m.inverse().is_none());
+    }
+
+    #[test]
+    fn motion_box_contains_the_child_at_all_times() {
+        // An off-center block swinging through a large rotation, so the
+        // corners travel on arcs well away from the sampled chords.
+        let (p0, p1) = (
+            Point3::construct(&[2.0, -1.0, -1.0]),
+            Point3::construct(&[4.0, 1.0, 1.0]),
+        );
+        let child = Arc::new(Box::construct(
+            &p0,
+            &p1,
+            Arc::new(Lambertian::construct(&Color3::new())),
+        ));
+        let track = TransformTrack::construct(
+            Track::construct(
+                &[
+                    Keyframe::construct(0.0, Vec3::new()),
+                    Keyframe::construct(0.5, Vec3::construct(&[1.0, 3.0, 0.0])),
+                    Keyframe::construct(1.0, Vec3::construct(&[-2.0, 0.0, 1.0])),
+                ],
+                Interpolation::CatmullRom,
+            ),
+            Track::construct(
+                &[
+                    Keyframe::construct(0.0, Vec3::new()),
+                    Keyframe::construct(1.0, Vec3::construct(&[20.0, 170.0, 0.0])),
+                ],
+                Interpolation::Linear,
+            ),
+            Track::constant(Vec3::construct(&[1.0, 1.0, 1.0])),
+        );
+        let motion = MotionTransform::construct(child, track.clone());
+        for (time0, time1) in [(0.0, 1.0), (0.2, 0.7)].iter() {
+            let mut bbox = Aabb::default();
+            assert!(motion.bounding_box(*time0, *time1, &mut bbox));
+            for i in 0..=1000 {
+                let time = time0 + (time1 - time0) * i as f64 / 1000.0;
+                let transform = track.at(time);
+                for c in 0..8 {
+                    let corner = Point3::construct(&[
+                        if c & 1 == 0 { p0.x() } else { p1.x() },
+                        if c & 2 == 0 { p0.y() } else { p1.y() },
+                        if c & 4 == 0 { p0.z() } else { p1.z() },
+                    ]);
+                    let p = transform.apply_point(&corner);
+                    for a in 0..3 {
+                        assert!(p.e[a] >= bbox.minimum().e[a] - 1e-9);
+                        assert!(p.e[a] <= bbox.maximum().e[a] + 1e-9);
+                    }
+                }
+            }
+        }
+    }
+}
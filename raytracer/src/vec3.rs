@@ -38,6 +38,10 @@ impl Vec3 {
     pub fn z(&self) -> f64 {
         self.e[2]
     }
+    pub fn luminance(&self) -> f64 {
+        // Rec. 709 weights, for treating a color as a scalar.
+        0.2126 * self.e[0] + 0.7152 * self.e[1] + 0.0722 * self.e[2]
+    }
     pub fn rgb(&self) -> [u8; 3] {
         [
             (255.999 * self.e[0]) as u8,
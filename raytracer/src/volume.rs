@@ -0,0 +1,285 @@
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::{next_material_id, Material, ScatterRecord};
+use crate::pdf::{henyey_greenstein, HenyeyGreensteinPdf};
+use crate::perlin::Perlin;
+use crate::ray::Ray;
+use crate::rt_weekend::random_double;
+use crate::texture::{SolidColor, Texture};
+use crate::vec3::{dot, Color3, Point3, Vec3};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+// Dense voxel grid of densities covering the unit cube, x varying fastest.
+#[derive(Debug)]
+pub struct DensityGrid {
+    pub nx: usize,
+    pub ny: usize,
+    pub nz: usize,
+    pub data: Vec<f64>,
+    pub max_density: f64,
+}
+impl DensityGrid {
+    pub fn construct(nx: usize, ny: usize, nz: usize, data: Vec<f64>) -> Self {
+        assert_eq!(data.len(), nx * ny * nz, "Density grid size mismatch.");
+        let max_density = data.iter().cloned().fold(0.0, f64::max);
+        Self {
+            nx,
+            ny,
+            nz,
+            data,
+            max_density,
+        }
+    }
+    pub fn load_ascii<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        // "nx ny nz" followed by nx * ny * nz whitespace separated densities.
+        let text = fs::read_to_string(path)?;
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_owned());
+        let mut tokens = text.split_whitespace();
+        let mut dims = [0usize; 3];
+        for d in dims.iter_mut() {
+            *d = tokens
+                .next()
+                .and_then(|t| t.parse().ok())
+                .ok_or_else(|| invalid("Missing grid resolution."))?;
+        }
+        let data = tokens
+            .map(|t| t.parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()
+            .map_err(|_| invalid("Invalid density value."))?;
+        if data.len() != dims[0] * dims[1] * dims[2] {
+            return Err(invalid("Density count does not match resolution."));
+        }
+        Ok(Self::construct(dims[0], dims[1], dims[2], data))
+    }
+    pub fn load_raw<P: AsRef<Path>>(path: P, nx: usize, ny: usize, nz: usize) -> io::Result<Self> {
+        // Headerless little-endian f32 densities.
+        let bytes = fs::read(path)?;
+        if bytes.len() != 4 * nx * ny * nz {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Raw grid size does not match resolution.",
+            ));
+        }
+        let data = bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64)
+            .collect();
+        Ok(Self::construct(nx, ny, nz, data))
+    }
+    pub fn from_turbulence(noise: &Perlin, resolution: usize, frequency: f64) -> Self {
+        let n = resolution;
+        let mut data = Vec::with_capacity(n * n * n);
+        for k in 0..n {
+            for j in 0..n {
+                for i in 0..n {
+                    let p = Point3::construct(&[
+                        (i as f64 + 0.5) / n as f64,
+                        (j as f64 + 0.5) / n as f64,
+                        (k as f64 + 0.5) / n as f64,
+                    ]);
+                    data.push(noise.turb(&(frequency * p)));
+                }
+            }
+        }
+        Self::construct(n, n, n, data)
+    }
+
+    fn voxel(&self, i: usize, j: usize, k: usize) -> f64 {
+        self.data[(k * self.ny + j) * self.nx + i]
+    }
+    pub fn lookup(&self, local: &Point3) -> f64 {
+        // Trilinear interpolation between voxel centers; zero outside.
+        if (0..3).any(|a| local.e[a] < 0.0 || local.e[a] > 1.0) {
+            return 0.0;
+        }
+        let dims = [self.nx, self.ny, self.nz];
+        let mut lo = [0usize; 3];
+        let mut frac = [0.0; 3];
+        for a in 0..3 {
+            let x = (local.e[a] * dims[a] as f64 - 0.5).max(0.0);
+            lo[a] = (x.floor() as usize).min(dims[a] - 1);
+            frac[a] = if lo[a] + 1 < dims[a] {
+                x - lo[a] as f64
+            } else {
+                0.0
+            };
+        }
+        let mut accum = 0.0;
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let i = (lo[0] + di).min(self.nx - 1);
+                    let j = (lo[1] + dj).min(self.ny - 1);
+                    let k = (lo[2] + dk).min(self.nz - 1);
+                    let w = (if di == 1 { frac[0] } else { 1.0 - frac[0] })
+                        * (if dj == 1 { frac[1] } else { 1.0 - frac[1] })
+                        * (if dk == 1 { frac[2] } else { 1.0 - frac[2] });
+                    accum += w * self.voxel(i, j, k);
+                }
+            }
+        }
+        accum
+    }
+}
+
+#[derive(Debug)]
+pub struct GridMedium {
+    pub grid: Arc<DensityGrid>,
+    pub bbox: Aabb,
+    pub density_scale: f64,
+    pub albedo: Color3,
+    pub g: f64,
+    pub emission: Arc<dyn Texture>,
+    pub id: u32,
+}
+impl GridMedium {
+    fn local(&self, p: &Point3) -> Point3 {
+        let min = self.bbox.minimum();
+        let extent = self.bbox.maximum() - min;
+        let d = *p - min;
+        Point3::construct(&[d.x() / extent.x(), d.y() / extent.y(), d.z() / extent.z()])
+    }
+    pub fn sigma_t(&self, p: &Point3) -> f64 {
+        self.density_scale * self.grid.lookup(&self.local(p))
+    }
+    pub fn majorant(&self) -> f64 {
+        self.density_scale * self.grid.max_density
+    }
+}
+impl Material for GridMedium {
+    fn scatter(&self, r_in: &Ray, _rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+        srec.is_specular = false;
+        srec.is_medium = true;
+        srec.attenuation = self.albedo;
+        srec.pdf_ptr = Some(Arc::new(HenyeyGreensteinPdf::construct(
+            &r_in.direction(),
+            self.g,
+        )));
+        true
+    }
+    fn emitted(&self, _r_in: &Ray, _rec: &HitRecord, u: f64, v: f64, p: &Point3) -> Color3 {
+        // Collision estimator: a real collision is an absorption with
+        // probability 1 - albedo, which is where emission is picked up.
+        let absorbed = Color3::construct(&[
+            1.0 - self.albedo.x(),
+            1.0 - self.albedo.y(),
+            1.0 - self.albedo.z(),
+        ]);
+        absorbed * self.emission.value(u, v, p)
+    }
+    fn scattering_pdf(&self, r_in: &Ray, _rec: &HitRecord, scattered: &Ray) -> f64 {
+        let cosine = dot(&r_in.direction().unit(), &scattered.direction().unit());
+        henyey_greenstein(cosine, self.g)
+    }
+    fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+// Heterogeneous participating medium filling an axis-aligned box.
+#[derive(Debug)]
+pub struct GridVolume {
+    pub medium: Arc<GridMedium>,
+}
+impl GridVolume {
+    pub fn construct(
+        grid: Arc<DensityGrid>,
+        min: &Point3,
+        max: &Point3,
+        density_scale: f64,
+        albedo: &Color3,
+        g: f64,
+    ) -> Self {
+        Self::construct_emissive(
+            grid,
+            min,
+            max,
+            density_scale,
+            albedo,
+            g,
+            Arc::new(SolidColor::construct(&Color3::new())),
+        )
+    }
+    pub fn construct_emissive(
+        grid: Arc<DensityGrid>,
+        min: &Point3,
+        max: &Point3,
+        density_scale: f64,
+        albedo: &Color3,
+        g: f64,
+        emission: Arc<dyn Texture>,
+    ) -> Self {
+        Self {
+            medium: Arc::new(GridMedium {
+                grid,
+                bbox: Aabb::construct(min, max),
+                density_scale,
+                albedo: *albedo,
+                g,
+                emission,
+                id: next_material_id(),
+            }),
+        }
+    }
+}
+impl Hittable for GridVolume {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        // Delta tracking against the grid's maximum density.
+        let (mut t, t_end) = match self.medium.bbox.hit_interval(r, t_min, t_max) {
+            Some(interval) => interval,
+            None => return false,
+        };
+        let majorant = self.medium.majorant();
+        if majorant <= 0.0 {
+            return false;
+        }
+        let ray_length = r.direction().length();
+        loop {
+            t -= (1.0 - random_double()).ln() / (majorant * ray_length);
+            if t >= t_end {
+                return false;
+            }
+            let p = r.at(t);
+            if random_double() < self.medium.sigma_t(&p) / majorant {
+                rec.t = t;
+                rec.p = p;
+                rec.normal = Vec3::construct(&[1.0, 0.0, 0.0]); // arbitrary
+                rec.time = r.time();
+                rec.shading_normal = rec.normal;
+                rec.front_face = true; // also arbitrary
+                rec.u = 0.0;
+                rec.v = 0.0;
+                rec.mat_ptr = Some(self.medium.clone());
+                return true;
+            }
+        }
+    }
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut Aabb) -> bool {
+        *output_box = self.medium.bbox;
+        true
+    }
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64) -> f64 {
+        // Ratio tracking: unbiased transmittance along [t_min, t_max], which
+        // unlike delta tracking does not stop at the first collision.
+        let (mut t, t_end) = match self.medium.bbox.hit_interval(r, t_min, t_max) {
+            Some(interval) => interval,
+            None => return 1.0,
+        };
+        let majorant = self.medium.majorant();
+        if majorant <= 0.0 {
+            return 1.0;
+        }
+        let ray_length = r.direction().length();
+        let mut tr = 1.0;
+        loop {
+            t -= (1.0 - random_double()).ln() / (majorant * ray_length);
+            if t >= t_end {
+                return tr;
+            }
+            tr *= 1.0 - self.medium.sigma_t(&r.at(t)) / majorant;
+        }
+    }
+}